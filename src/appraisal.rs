@@ -0,0 +1,195 @@
+//! ゲーム内の鑑定(星の数と3本のバー)や個体値の合計の割合から、個体値の候補を絞り込む
+//!
+//! 星の数は個体値の合計で決まる(0: 0～22, 1: 23～29, 2: 30～36, 3: 37～44, 4: 45)。
+//! 4は100%のときの赤い星3つ。
+//! バーは5ごとに区切られた3つの目盛りで、埋まっている目盛りの数を0～3で入力する(3は満タン)。
+
+use std::fmt;
+
+use crate::pokepedia::Pokepedia;
+use crate::pokemon::{IVs, calc_lv};
+use crate::index::i2ivs;
+
+/// 星の数ごとの個体値の合計の範囲
+const STAR_RANGES: [(i32, i32); 5] = [(0, 22), (23, 29), (30, 36), (37, 44), (45, 45)];
+
+/// 鑑定結果。わからないものはNone
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Appraisal {
+    pub stars: Option<i32>,  // 星の数(0～4)
+    pub bars: Option<[i32; 3]>,  // 攻撃、防御、HPのバーの埋まっている目盛りの数(0～3)
+    pub percent: Option<(f64, f64)>,  // 個体値の合計の割合(%)の範囲
+}
+
+impl Appraisal {
+    /// ivsが鑑定結果と矛盾しないか
+    pub fn matches(&self, ivs: IVs) -> bool {
+        let total = ivs.attack + ivs.defense + ivs.stamina;
+
+        if let Some(stars) = self.stars {
+            let (min, max) = STAR_RANGES[stars as usize];
+            if !(min..=max).contains(&total) {
+                return false;
+            }
+        }
+
+        if let Some(bars) = self.bars {
+            let ivs = [ivs.attack, ivs.defense, ivs.stamina];
+            if !ivs.iter().zip(bars.iter()).all(|(iv, bar)| bar_of_iv(*iv) == *bar) {
+                return false;
+            }
+        }
+
+        if let Some((min, max)) = self.percent {
+            // ゲームの表示に合わせて整数に丸めた割合で比べる
+            let percent = (total as f64 / 45.0 * 100.0).round();
+            if percent < min || max < percent {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// 個体値がバーの目盛りいくつ分か
+fn bar_of_iv(iv: i32) -> i32 {
+    if iv == 15 { 3 } else { iv / 5 }
+}
+
+impl fmt::Display for Appraisal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let mut v = vec![];
+
+        if let Some(stars) = self.stars {
+            v.push(format!("星{}", stars));
+        }
+
+        if let Some(bars) = self.bars {
+            v.push(format!("バー({}, {}, {})", bars[0], bars[1], bars[2]));
+        }
+
+        if let Some((min, max)) = self.percent {
+            v.push(format!("{}～{}%", min, max));
+        }
+
+        write!(f, "{}", v.join(" "))
+    }
+}
+
+/// "3"(星の数)、"3 2 2 3"(星の数と攻撃、防御、HPのバー)、"80-90%"や"91%"(個体値の合計の割合)。
+/// 星の数と割合は"2 60-70%"のように一緒に書ける
+impl std::str::FromStr for Appraisal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut appraisal = Appraisal::default();
+        let mut nums = vec![];
+
+        for token in s.split_whitespace() {
+            match token.strip_suffix('%') {
+                Some(range) => {
+                    let (min, max) = match range.split_once('-') {
+                        None => (range, range),
+                        Some((min, max)) => (min, max),
+                    };
+
+                    match (min.parse::<f64>(), max.parse::<f64>()) {
+                        (Ok(min), Ok(max)) if 0.0 <= min && min <= max && max <= 100.0 => appraisal.percent = Some((min, max)),
+                        _ => return Err(format!("割合は0～100の\"80-90%\"のような範囲: {}", token)),
+                    }
+                },
+                None => match token.parse::<i32>() {
+                    Ok(n) => nums.push(n),
+                    Err(_) => return Err(format!("数字か割合で入力する: {}", token)),
+                },
+            }
+        }
+
+        match nums.len() {
+            0 => (),
+            1 | 4 => {
+                if !(0..=4).contains(&nums[0]) {
+                    return Err(format!("星の数は0～4: {}", nums[0]));
+                }
+
+                appraisal.stars = Some(nums[0]);
+
+                if nums.len() == 4 {
+                    if nums[1..].iter().any(|n| !(0..=3).contains(n)) {
+                        return Err(String::from("バーの目盛りは0～3"));
+                    }
+
+                    appraisal.bars = Some([nums[1], nums[2], nums[3]]);
+                }
+            },
+            _ => return Err(String::from("星の数だけか、星の数と3本のバーを入力する")),
+        }
+
+        if appraisal == Appraisal::default() {
+            return Err(String::from("鑑定結果が空"));
+        }
+
+        Ok(appraisal)
+    }
+}
+
+/// 鑑定結果とCP、HP(わかれば)から個体値の候補を絞り込む
+pub fn candidate_ivs(dict: &Pokepedia, cp: i32, hp: Option<i32>, appraisal: &Appraisal) -> Vec<IVs> {
+    (0..(16*16*16)).map(i2ivs).filter(|ivs| appraisal.matches(*ivs)).filter(|ivs| {
+        match calc_lv(dict, cp, *ivs) {
+            None => false,
+            Some(lv) => match hp {
+                None => true,
+                Some(hp) => dict.base_stats().stats(lv, *ivs).stamina.floor() as i32 == hp,
+            },
+        }
+    }).collect()
+}
+
+/// 個体値の候補の範囲を"IVs(10-12,  9, 12-14)"のように表す
+pub fn format_ivs_range(candidates: &[IVs]) -> String {
+    let range = |f: fn(&IVs) -> i32| {
+        let min = candidates.iter().map(f).min().unwrap_or(0);
+        let max = candidates.iter().map(f).max().unwrap_or(0);
+
+        if min == max { format!("{:>2}", min) } else { format!("{:>2}-{:>2}", min, max) }
+    };
+
+    format!("IVs({}, {}, {})", range(|ivs| ivs.attack), range(|ivs| ivs.defense), range(|ivs| ivs.stamina))
+}
+
+#[test]
+fn test_appraisal() {
+    use crate::pokepedia::pokepedia_by_name;
+
+    assert_eq!("3".parse(), Ok(Appraisal { stars: Some(3), ..Appraisal::default() }));
+    assert_eq!("2 1 3 2".parse(), Ok(Appraisal { stars: Some(2), bars: Some([1, 3, 2]), percent: None }));
+    assert_eq!("80-90%".parse(), Ok(Appraisal { percent: Some((80.0, 90.0)), ..Appraisal::default() }));
+    assert!("5".parse::<Appraisal>().is_err());
+    assert!("2 1 4 2".parse::<Appraisal>().is_err());
+    assert!("2 1".parse::<Appraisal>().is_err());
+    assert!("90-80%".parse::<Appraisal>().is_err());
+    assert!("".parse::<Appraisal>().is_err());
+
+    let ivs = IVs::new(10, 9, 12).unwrap();  // 合計31(69%)
+    assert!("2 2 1 2".parse::<Appraisal>().unwrap().matches(ivs));
+    assert!("69%".parse::<Appraisal>().unwrap().matches(ivs));
+    assert!(!"3".parse::<Appraisal>().unwrap().matches(ivs));
+    assert!(!"2 2 2 2".parse::<Appraisal>().unwrap().matches(ivs));
+    assert!("4".parse::<Appraisal>().unwrap().matches(IVs::new(15, 15, 15).unwrap()));
+
+    // CPとHPで候補が絞られて、本当の個体値は必ず残る
+    let koko = pokepedia_by_name("ココロモリ").unwrap();
+    let appraisal = "2 2 1 2".parse::<Appraisal>().unwrap();
+    let by_cp = candidate_ivs(koko, 1489, None, &appraisal);
+    let by_hp = candidate_ivs(koko, 1489, Some(135), &appraisal);
+
+    assert!(by_hp.contains(&ivs));
+    assert!(by_hp.len() <= by_cp.len());
+    assert!(by_cp.iter().all(|ivs| appraisal.matches(*ivs) && calc_lv(koko, 1489, *ivs).is_some()));
+    assert!(candidate_ivs(koko, 1489, Some(10), &appraisal).is_empty());
+
+    assert_eq!(format_ivs_range(&[ivs]), "IVs(10,  9, 12)");
+    assert_eq!(format_ivs_range(&[ivs, IVs::new(12, 9, 11).unwrap()]), "IVs(10-12,  9, 11-12)");
+}
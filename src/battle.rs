@@ -2,29 +2,29 @@
 //! AIでの自動対戦のみに対応する。エミュレーションはしない。
 
 use std::cmp::Ordering;
+use std::fmt;
 use std::sync::Arc;
 
 use rand::prelude::*;
+use serde::{Serialize, Deserialize};
 
 use crate::pokepedia::Pokepedia;
 use crate::pokemon::{Pokemon, Stats, IVs};
 use crate::types::Type;
 use crate::moves::{FastMove, ChargeMove, Buff};
-
-macro_rules! debug_print {
-    ($($arg:tt)*) => (if ::std::cfg!(debug_assertions) { ::std::print!($($arg)*); })
-}
-
-macro_rules! debug_println {
-    ($($arg:tt)*) => (if ::std::cfg!(debug_assertions) { ::std::println!($($arg)*); })
-}
+use crate::strategy::{BattleStrategy, DefaultStrategy};
+use crate::utils::jp_fixed_width_string;
+use crate::observer::{BattleObserver, BattleEvent, NullObserver};
 
 pub const MS_PER_TURN: i32 = 500;  // 500 ミリ秒/ターン
 pub const TURN_PER_SEC: i32 = 2;  // 2 ターン/秒
 pub const LIMIT_TURN: i32 = (4 * 60 + 30) * TURN_PER_SEC;  // 制限ターン数(4分30秒)
 
+/// バトルで使う乱数生成器。ランダムな判定はすべてこれを通す。
+pub type BattleRng = StdRng;
+
 /// プレイヤーがとることのできる行動
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Action {
     FastMove,  // ノーマルアタック
     ChargeMove(u8),  // スペシャルアタック([data] 0: わざ1, 1: わざ2)
@@ -33,7 +33,34 @@ pub enum Action {
            // 硬直、相手がスペシャルアタック中、相手が交換ポケモン選択中などで待機のときにも自動設定
 }
 
-#[derive(Debug, Clone)]
+/// 記録やコマンドで使う文字列。fast, cm1, cm2, sw0～sw2, none
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Action::FastMove => write!(f, "fast"),
+            Action::ChargeMove(i) => write!(f, "cm{}", i + 1),
+            Action::SwitchPokemon(i) => write!(f, "sw{}", i),
+            Action::None => write!(f, "none"),
+        }
+    }
+}
+
+impl std::str::FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(Action::FastMove),
+            "cm1" => Ok(Action::ChargeMove(0)),
+            "cm2" => Ok(Action::ChargeMove(1)),
+            "none" => Ok(Action::None),
+            _ => s.strip_prefix("sw").and_then(|i| i.parse().ok()).map(Action::SwitchPokemon)
+                .ok_or_else(|| format!("不正な行動: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Phase {
     Neutral,
     TimeOver(u8),  // 時間オーバー([data] 0: プレイヤー0の勝利, 1: プレイヤー1の勝利, 2: 引き分け)
@@ -43,17 +70,18 @@ pub enum Phase {
 pub struct Battle {
     pub states: Vec<State>,  // 各状態。一度登録した状態は変更しない。
     pub actions: Vec<[Action; 2]>,  // 行動
+    pub logs: Vec<Log>,  // 各ターンの記録。actionsと同じ数だけある。
+
+    pub strategies: [Box<dyn BattleStrategy>; 2],  // 各プレイヤーの戦略
 
-    pub strategy0: fn(&Player) -> Action,
-    pub shield0: fn(&Player) -> bool,
-    pub switch0: fn(&Player) -> usize,
+    pub seed: u64,  // 乱数の種。同じ種と行動なら同じ結果になる。
+    rng: BattleRng,
+    rngs: Vec<BattleRng>,  // 各状態になったときの乱数生成器。statesと同じ数だけある。巻き戻しに使う
 
-    pub strategy1: fn(&Player) -> Action,
-    pub shield1: fn(&Player) -> bool,
-    pub switch1: fn(&Player) -> usize,
+    observers: Vec<Box<dyn BattleObserver>>,  // バトル中の出来事を知らせる相手
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct State {
     pub player0: Player,
     pub player1: Player,
@@ -61,9 +89,97 @@ pub struct State {
 
     pub turn: i32,  // 現在のターン数。実際に行動した単位。
     pub elapsed_ms: i32,  // 経過時間(ミリ秒)
+
+    pub rules: BattleRules,  // バトルのルール
+}
+
+/// バトルのルール。既定値はGOバトルリーグ
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BattleRules {
+    pub limit_turn: i32,  // 制限ターン数。超えたら残りHPの合計が多いほうが勝ち
+    pub charge_move_ms: i32,  // スペシャルアタック1回にかかるミリ秒。経過時間にだけ加え、ターンは進めない
+    pub switch_ms: i32,  // ポケモンを倒されて次のポケモンを選ぶのにかかるミリ秒
+    pub trainer_battle_bonus: f64,  // トレーナーバトルボーナス。すべてのダメージに掛ける
+    pub num_shields: i32,  // 各プレイヤーのシールドの数
+    pub team_size: usize,  // チームのポケモンの数
+    pub cm_bonus: f64,  // スペシャルアタックのミニゲームの出来(0.0～1.0)。ダメージに掛ける
+    pub fast_move_timing: FastMoveTiming,  // ノーマルアタックのダメージが入るタイミング
+}
+
+impl Default for BattleRules {
+    fn default() -> Self {
+        BattleRules {
+            limit_turn: LIMIT_TURN,
+            charge_move_ms: CHARGE_MOVE_MS,
+            switch_ms: SWITCH_MS,
+            trainer_battle_bonus: TRAINER_BATTLE_BONUS,
+            num_shields: 2,
+            team_size: 3,
+            cm_bonus: 1.0,
+            fast_move_timing: FastMoveTiming::Classic,
+        }
+    }
+}
+
+/// 名前のついたルール。[名前, 説明]
+pub const RULES_NAMES: [[&str; 2]; 3] = [
+    ["gbl", "GOバトルリーグ"],
+    ["noshield", "シールドなし"],
+    ["1v1", "1匹どうし"],
+];
+
+impl BattleRules {
+    /// RULES_NAMESの名前からルールを作る
+    pub fn by_name(name: &str) -> Option<Self> {
+        let rules = Self::default();
+
+        match name {
+            "gbl" => Some(rules),
+            "noshield" => Some(BattleRules { num_shields: 0, ..rules }),
+            "1v1" => Some(BattleRules { team_size: 1, ..rules }),
+            _ => None,
+        }
+    }
+}
+
+/// ノーマルアタックのダメージが入るタイミング
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FastMoveTiming {
+    /// 1ターン技は使ったターンにランダムな順で、それ以外は硬直の最後のターンにスペシャルアタックの後で入る。
+    /// 硬直中に相手のスペシャルアタックを受けると、その後にダメージが入る。
+    #[default]
+    Classic,
+
+    /// 本物のGOバトルリーグと同じく、どのノーマルアタックも最後のターンの終わりにダメージが入る。
+    /// 同じターンに終わるノーマルアタックは同時に入り、スペシャルアタックより先に処理される。
+    /// 硬直中に相手のスペシャルアタックを受けると、その前に終わったものとしてダメージが入る。
+    Real,
 }
 
-#[derive(Clone)]
+impl fmt::Display for FastMoveTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            FastMoveTiming::Classic => write!(f, "classic"),
+            FastMoveTiming::Real => write!(f, "real"),
+        }
+    }
+}
+
+impl std::str::FromStr for FastMoveTiming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(FastMoveTiming::Classic),
+            "real" => Ok(FastMoveTiming::Real),
+            _ => Err(format!("不正なタイミング: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Player {
     pub name: String,
     pub team: Vec<BattlePokemon>,
@@ -76,7 +192,7 @@ pub struct Player {
     pub dur_turns: i32,  // 硬直ターン数。毎ターンの最初に-1されて、0なら行動可能
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BattlePokemon {
     pub poke: Arc<Pokemon>,
 
@@ -86,33 +202,85 @@ pub struct BattlePokemon {
     pub is_disable_type_effect: bool,  // タイプ相性を無効にする。PPT(Power Per Turn)の計算に使用
 }
 
-#[derive(Clone)]
+/// バトル中に起きた出来事
+#[derive(Debug, Clone)]
+pub enum Event {
+    FastMove(&'static FastMove, i32),  // ノーマルアタック([data] わざ, ダメージ)
+    ChargeMove(&'static ChargeMove, i32, bool),  // スペシャルアタック([data] わざ, ダメージ, ステータス変化が起きたか)
+    Shield,  // シールドを使った
+    Faint,  // 瀕死になった
+    Switch(&'static str),  // ポケモンを交代([data] 出てきたポケモン)
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Event::FastMove(mv, damage) => write!(f, "{}({})", mv.name(), damage),
+            Event::ChargeMove(mv, damage, true) => write!(f, "*{}*({}, ステータス変化)", mv.name(), damage),
+            Event::ChargeMove(mv, damage, false) => write!(f, "*{}*({})", mv.name(), damage),
+            Event::Shield => write!(f, "シールド"),
+            Event::Faint => write!(f, "瀕死"),
+            Event::Switch(name) => write!(f, "交代 {}", name),
+        }
+    }
+}
+
+/// 1ターン分の記録。ターン終了時の状態とそのターンに起きた出来事を持つ。
+#[derive(Debug, Clone)]
 pub struct Log {
     pub turn: i32,
 
+    pub poke0: &'static str,
     pub hp0: i32,
     pub energy0: i32,
-    pub event0: String,
+    pub num_shields0: i32,
+    pub buff0: (i32, i32),
+    pub events0: Vec<Event>,
 
+    pub poke1: &'static str,
     pub hp1: i32,
     pub energy1: i32,
-    pub event1: String,
+    pub num_shields1: i32,
+    pub buff1: (i32, i32),
+    pub events1: Vec<Event>,
 }
 
-fn strategy(player: &Player) -> Action {
-    if player.poke().can_charge_move1() {
-        Action::ChargeMove(0)
-    } else {
-        Action::FastMove
+impl Log {
+    pub fn new(state: &State, events: [Vec<Event>; 2]) -> Self {
+        let [events0, events1] = events;
+        let p0 = &state.player0;
+        let p1 = &state.player1;
+
+        Log {
+            turn: state.turn,
+
+            poke0: p0.poke_name(),
+            hp0: p0.poke().hp(),
+            energy0: p0.poke().energy,
+            num_shields0: p0.num_shields,
+            buff0: p0.poke().buff,
+            events0,
+
+            poke1: p1.poke_name(),
+            hp1: p1.poke().hp(),
+            energy1: p1.poke().energy,
+            num_shields1: p1.num_shields,
+            buff1: p1.poke().buff,
+            events1,
+        }
     }
-}
 
-fn shield_strategy(_player: &Player) -> bool {
-    true
-}
+    /// widthはポケモン名の表示幅
+    pub fn format(&self, width: usize) -> String {
+        let events0 = self.events0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ");
+        let events1 = self.events1.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ");
 
-fn switch_strategy(_player: &Player) -> usize {
-    0
+        format!("{:>3} {} HP {:>3} E {:>3} S {} B({:>2}, {:>2}) | {} HP {:>3} E {:>3} S {} B({:>2}, {:>2}) | {} | {}",
+                self.turn,
+                jp_fixed_width_string(self.poke0, width), self.hp0, self.energy0, self.num_shields0, self.buff0.0, self.buff0.1,
+                jp_fixed_width_string(self.poke1, width), self.hp1, self.energy1, self.num_shields1, self.buff1.0, self.buff1.1,
+                events0, events1)
+    }
 }
 
 impl Battle {
@@ -122,60 +290,357 @@ impl Battle {
         let player0 = Player::new(name0, team0);
         let player1 = Player::new(name1, team1);
 
-        let start_state = State {
-            player0,
-            player1,
-            phase: Phase::Neutral,
-            turn: 0,
-            elapsed_ms: 0,
-        };
+        Self::from_state(State::new(player0, player1))
+    }
+
+    /// start_stateからバトルを始める。途中の局面から対戦させるときに使う。
+    pub fn from_state(start_state: State) -> Self {
+        let seed = rand::random();
+        let rng = BattleRng::seed_from_u64(seed);
 
         Battle {
             states: vec![start_state],
             actions: vec![],
+            logs: vec![],
+
+            strategies: [Box::new(DefaultStrategy), Box::new(DefaultStrategy)],
+
+            seed,
+            rngs: vec![rng.clone()],
+            rng,
+
+            observers: vec![],
+        }
+    }
+
+    /// 乱数の種を設定する。バトル開始前に呼ぶ。
+    pub fn set_seed(&mut self, seed: u64) {
+        assert!(self.actions.is_empty());
+
+        self.seed = seed;
+        self.rng = BattleRng::seed_from_u64(seed);
+        self.rngs = vec![self.rng.clone()];
+    }
+
+    /// バトル中の出来事を受け取るオブザーバーを追加する
+    pub fn add_observer(&mut self, observer: Box<dyn BattleObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// states[turn]まで巻き戻して、そこから別の戦略で進める新しいバトルを作る。
+    /// オブザーバーは引き継がない。
+    /// 乱数の状態もそのときに戻すので、元と同じ戦略で進めれば元と同じ結果になる。
+    pub fn branch(&self, turn: usize, strategies: [Box<dyn BattleStrategy>; 2]) -> Battle {
+        assert!(turn < self.states.len());
+
+        Battle {
+            states: self.states[..=turn].to_vec(),
+            actions: self.actions[..turn].to_vec(),
+            logs: self.logs[..turn].to_vec(),
+
+            strategies,
+
+            seed: self.seed,
+            rng: self.rngs[turn].clone(),
+            rngs: self.rngs[..=turn].to_vec(),
+
+            observers: vec![],
+        }
+    }
+
+    /// player_iの戦略を設定する
+    pub fn set_strategy(&mut self, player_i: usize, strategy: Box<dyn BattleStrategy>) {
+        self.strategies[player_i] = strategy;
+    }
 
-            strategy0: strategy,
-            shield0: shield_strategy,
-            switch0: switch_strategy,
+    /// player_iの開始時の条件を設定する。バトル開始前に呼ぶ。
+    pub fn set_player_options(&mut self, player_i: usize, options: &PlayerOptions) {
+        assert!(self.actions.is_empty());
 
-            strategy1: strategy,
-            shield1: shield_strategy,
-            switch1: switch_strategy,
+        options.apply(self.states[0].player_mut(player_i));
+    }
+
+    /// ルールを設定する。バトル開始前に呼ぶ。
+    /// 各プレイヤーのシールドの数をルールの数にし、ルールより多いポケモンはチームから外す。
+    pub fn set_rules(&mut self, rules: BattleRules) {
+        assert!(self.actions.is_empty());
+
+        let state = &mut self.states[0];
+        state.rules = rules;
+
+        for p in state.players_mut() {
+            p.num_shields = rules.num_shields;
+            p.team.truncate(rules.team_size.max(1));
+
+            if p.cur_poke >= p.team.len() {
+                p.cur_poke = 0;
+            }
         }
     }
 
+    /// player_iから見たバトルレーティング(0～1000)を返す。
+    /// 相手に与えたダメージの割合と自分の残りHPの割合をそれぞれ500点満点で評価する。
+    /// 500より大きければ勝ち、小さければ負けに近い。
+    pub fn rating(&self, player_i: usize) -> i32 {
+        self.state().rating(player_i).round() as i32
+    }
+
     pub fn state(&self) -> &State {
         self.states.last().unwrap()
     }
 
     pub fn start(&mut self) {
-        let mut act0;
-        let mut act1;
+        // 制限ターン数を超えると必ずTimeOverになるので無限ループにはならない
+        loop {
+            let actions = self.next_actions();
 
-        {
-            let state = self.state();
-            act0 = (self.strategy0)(&state.player0);
-            act1 = (self.strategy1)(&state.player1);
+            if !self.do_action(actions) {
+                break;
+            }
         }
+    }
 
-        let mut i = 0;
+    /// 各プレイヤーの戦略から次の行動を決める
+    pub fn next_actions(&mut self) -> [Action; 2] {
+        let state = self.states.last().unwrap();
 
-        while self.do_action([act0, act1]) {
-            let state = self.state();
-            act0 = (self.strategy0)(&state.player0);
-            act1 = (self.strategy1)(&state.player1);
+        let rng = &mut self.rng;
 
-            i += 1;
+        [self.strategies[0].action(state, 0, rng), self.strategies[1].action(state, 1, rng)]
+    }
 
-            if i > 100 {
-                break;
-            }
+    /// 勝者のプレイヤー番号を返す。引き分けなら2、バトル中ならNoneを返す。
+    pub fn winner(&self) -> Option<u8> {
+        match self.state().phase {
+            Phase::GameOver(i) | Phase::TimeOver(i) => Some(i),
+            Phase::Neutral => None,
         }
     }
 
     /// 戻り値: true: ゲーム継続中, false: ゲーム終了
     pub fn do_action(&mut self, actions: [Action; 2]) -> bool {
-        let mut state = self.state().clone();
+        let state = self.states.last().unwrap();
+
+        match state.phase {
+            Phase::GameOver(_) | Phase::TimeOver(_) => return false,
+
+            _ => (),
+        }
+
+        let mut decider = StrategyDecider { strategies: &mut self.strategies, rng: &mut self.rng };
+        let (state, events) = state.step_observed(actions, &mut decider, &mut self.observers);
+
+        let ret_val = !matches!(state.phase, Phase::GameOver(_) | Phase::TimeOver(_));
+
+        self.logs.push(Log::new(&state, events));
+        self.states.push(state);
+        self.actions.push(actions);
+        self.rngs.push(self.rng.clone());
+
+        ret_val
+    }
+}
+
+/// バトル中に必要になる判断。
+/// プレイヤーが選ぶこと(シールド、瀕死後の交代)と、確率で決まること(ステータス変化、同時に行動したときの先攻)をまとめる。
+/// 通常のバトルでは各プレイヤーの戦略と乱数で決め、探索では分岐を列挙するために差し替える。
+pub trait Decider {
+    /// player_iが相手のスペシャルアタックmvにシールドを使うか
+    fn shield(&mut self, state: &State, player_i: usize, mv: &'static ChargeMove) -> bool;
+
+    /// 瀕死になったplayer_iが次に出すポケモンのインデックス
+    fn switch(&mut self, state: &State, player_i: usize) -> usize;
+
+    /// 確率prob(0.0～1.0)で起きることが起きたか
+    fn chance(&mut self, prob: f64) -> bool;
+}
+
+/// 各プレイヤーの戦略とバトルの乱数で判断する
+pub struct StrategyDecider<'a> {
+    pub strategies: &'a mut [Box<dyn BattleStrategy>; 2],
+    pub rng: &'a mut BattleRng,
+}
+
+impl Decider for StrategyDecider<'_> {
+    fn shield(&mut self, state: &State, player_i: usize, mv: &'static ChargeMove) -> bool {
+        self.strategies[player_i].shield(state, player_i, mv, self.rng)
+    }
+
+    fn switch(&mut self, state: &State, player_i: usize) -> usize {
+        self.strategies[player_i].switch(state, player_i, self.rng)
+    }
+
+    fn chance(&mut self, prob: f64) -> bool {
+        self.rng.gen::<f64>() < prob
+    }
+}
+
+#[test]
+fn test_battle() {
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let mut battle = Battle::new(String::from("p0"), vec![p0.clone()], String::from("p1"), vec![p1, p2]);
+    battle.start();
+
+    assert!(battle.winner().is_some());
+    assert_eq!(battle.logs.len(), battle.actions.len());
+    assert_eq!(battle.states.len(), battle.actions.len() + 1);
+
+    // ブラッキーとナマズンが瀕死になるか、ココロモリが瀕死になるまで続く
+    let num_faints = battle.logs.iter()
+        .map(|log| log.events0.iter().chain(log.events1.iter()).filter(|e| matches!(e, Event::Faint)).count())
+        .sum::<usize>();
+    assert!(num_faints >= 1);
+
+    let last = battle.logs.last().unwrap();
+    assert!(last.hp0 == 0 || last.hp1 == 0 || matches!(battle.state().phase, Phase::TimeOver(_)));
+}
+
+#[test]
+fn test_battle_branch() {
+    use crate::strategy::StrategyKind;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let kinds = [StrategyKind::Random, StrategyKind::Random];
+    let mut battle = Battle::new(String::from("p0"), vec![p0, p2.clone()], String::from("p1"), vec![p1, p2]);
+    battle.set_strategy(0, kinds[0].create());
+    battle.set_strategy(1, kinds[1].create());
+    battle.set_seed(7);
+    battle.start();
+
+    // 同じ戦略で分岐させると元と同じ結果になる
+    let turn = battle.actions.len() / 2;
+    let mut branch = battle.branch(turn, [kinds[0].create(), kinds[1].create()]);
+    assert_eq!(branch.states.len(), turn + 1);
+    assert_eq!(branch.state(), &battle.states[turn]);
+
+    branch.start();
+    assert_eq!(branch.actions, battle.actions);
+    assert_eq!(branch.state(), battle.state());
+}
+
+#[test]
+fn test_battle_seed() {
+    use crate::strategy::StrategyKind;
+
+    let run = |seed| {
+        let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+        let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+        let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+        let mut battle = Battle::new(String::from("p0"), vec![p0, p2.clone()], String::from("p1"), vec![p1, p2]);
+        battle.set_strategy(0, StrategyKind::Random.create());
+        battle.set_strategy(1, StrategyKind::Random.create());
+        battle.set_seed(seed);
+        battle.start();
+        battle
+    };
+
+    let b0 = run(1);
+    let b1 = run(1);
+    assert_eq!(b0.seed, 1);
+    assert_eq!(b0.actions, b1.actions);
+    assert_eq!(b0.states, b1.states);
+
+    // 種が違えばどこかで違う結果になる
+    assert!((2..10).any(|seed| run(seed).actions != b0.actions));
+}
+
+#[test]
+fn test_fast_move_timing() {
+    let koko = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let nama = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let step = |state: &State, actions| {
+        let mut strategies: [Box<dyn BattleStrategy>; 2] = [Box::new(DefaultStrategy), Box::new(DefaultStrategy)];
+        let mut rng = BattleRng::seed_from_u64(0);
+        state.step(actions, &mut StrategyDecider { strategies: &mut strategies, rng: &mut rng }).0
+    };
+
+    // エアスラッシュの硬直中に、相手のスペシャルアタックで瀕死になる
+    let mut state = State::new(Player::new(String::from("p0"), vec![nama.clone()]),
+                               Player::new(String::from("p1"), vec![koko, nama.clone()]));
+    state.player0.poke_mut().energy = 100;
+    state.player1.num_shields = 0;
+    state.player1.in_fast_move = true;
+    state.player1.dur_turns = 1;
+    state.player1.poke_mut().hp = 1;
+
+    let actions = [Action::ChargeMove(0), Action::FastMove];
+    let max_hp = state.player0.poke().base_hp();
+
+    let classic = step(&state, actions);
+    assert_eq!(classic.player0.poke().hp(), max_hp);
+    assert_eq!(classic.player1.poke_name(), "ナマズン");
+
+    // 本物と同じタイミングなら、スペシャルアタックの前にノーマルアタックが入る
+    state.rules.fast_move_timing = FastMoveTiming::Real;
+    let real = step(&state, actions);
+    assert!(real.player0.poke().hp() < max_hp);
+    assert_eq!(real.player1.poke_name(), "ナマズン");
+
+    // 1ターン技で同時に倒しあうと、本物のタイミングなら両方とも次のポケモンに交代する
+    let mut state = State::new(Player::new(String::from("p0"), vec![nama.clone(), nama.clone()]),
+                               Player::new(String::from("p1"), vec![nama.clone(), nama]));
+    state.player0.poke_mut().hp = 1;
+    state.player1.poke_mut().hp = 1;
+
+    let classic = step(&state, [Action::FastMove, Action::FastMove]);
+    assert_eq!(classic.player0.cur_poke + classic.player1.cur_poke, 1);
+
+    state.rules.fast_move_timing = FastMoveTiming::Real;
+    let real = step(&state, [Action::FastMove, Action::FastMove]);
+    assert_eq!((real.player0.cur_poke, real.player1.cur_poke), (1, 1));
+
+    assert_eq!("real".parse::<FastMoveTiming>(), Ok(FastMoveTiming::Real));
+    assert_eq!(FastMoveTiming::Classic.to_string(), "classic");
+}
+
+pub const CHARGE_MOVE_MS: i32 = 20 * MS_PER_TURN;
+/// ポケモンを倒されてプレイヤーが次のポケモンを選ぶのにかかった時間をこれと仮定
+pub const SWITCH_MS: i32 = 10 * MS_PER_TURN;
+
+impl State {
+    /// バトル開始時の状態を作る。各プレイヤーのポケモンのHPやエネルギーなどはそのまま使う。
+    pub fn new(player0: Player, player1: Player) -> Self {
+        State {
+            player0,
+            player1,
+            phase: Phase::Neutral,
+            turn: 0,
+            elapsed_ms: 0,
+            rules: BattleRules::default(),
+        }
+    }
+
+    fn elapsed_ms(&self) -> i32 {
+        self.turn * MS_PER_TURN
+    }
+
+    /// player_iから見たバトルレーティング(0.0～1000.0)。Battle::ratingを参照
+    pub fn rating(&self, player_i: usize) -> f64 {
+        let hp_ratio = |p: &Player| {
+            let max_hp = p.team.iter().map(|poke| poke.base_hp()).sum::<i32>();
+            p.sum_hp() as f64 / max_hp as f64
+        };
+
+        500.0 * (1.0 - hp_ratio(self.opponent(player_i))) + 500.0 * hp_ratio(self.player(player_i))
+    }
+
+    /// 両プレイヤーの行動actionsで1ターン進めた状態と、そのターンに起きた出来事を返す。
+    /// 途中で必要になる判断はdeciderにまかせる。
+    pub fn step(&self, actions: [Action; 2], decider: &mut dyn Decider) -> (State, [Vec<Event>; 2]) {
+        self.step_observed(actions, decider, &mut NullObserver)
+    }
+
+    /// stepと同じだが、起きた出来事をobsに知らせる
+    pub fn step_observed(&self, actions: [Action; 2], decider: &mut dyn Decider, obs: &mut dyn BattleObserver) -> (State, [Vec<Event>; 2]) {
+        let mut state = self.clone();
 
         // 1ターン技か？
         let mut use_1turn_move = [false, false];
@@ -183,91 +648,69 @@ impl Battle {
         // スペシャルアタック使用確認。 0: わざ1, 1: わざ2, 2: 使用しない
         let mut use_charge_move: [usize; 2] = [2, 2];
 
-        match state.phase {
-            Phase::GameOver(_) | Phase::TimeOver(_) => {
-                debug_println!("Debug: 無意味なdo_action呼び出し, phase = {:?}", state.phase);
-                return false;
-            },
+        let mut events: [Vec<Event>; 2] = [vec![], vec![]];
 
-            _ => (),
+        if state.phase != Phase::Neutral {
+            return (state, events);
         }
 
-        debug_println!();
-        debug_println!("Debug: turn {} start [time: {} s] ---------------------------", state.turn, (state.elapsed_ms as f32 / 1000.0));
-        debug_println!("Debug: action = [{:?}, {:?}], phase = {:?}", actions[0], actions[1], state.phase);
+        // 行動の処理中はstateを借用しているので、出来事は後でまとめて知らせる
+        let mut action_events = vec![];
 
-        for p in state.players() {
-            debug_println!("Debug: player {}: dur_turns = {}, switch_ms = {}, num_shields = {}", p.name, p.dur_turns, p.switch_ms, p.num_shields);
+        for (player_i, p) in state.players_mut().into_iter().enumerate() {
+            // 硬直中に交代やスペシャルアタックをすると、硬直後に実行される。
+            // 硬直中に交代とスペシャルアタックを両方すると、硬直後に代する。
 
-            let poke = p.poke();
-            debug_print!("Debug:   {}: HP = {} / {}, energy = {}, buff = {:?}", p.poke_name(), poke.hp(), poke.base_hp(), poke.energy, poke.buff);
-            debug_println!(", in_fast_move = {}, dur_turns =  {}", p.in_fast_move, p.dur_turns);
-        }
-
-        debug_println!();
+            match actions[player_i] {
+                Action::None => continue,
 
-        match state.phase {
-            Phase::GameOver(_) | Phase::TimeOver(_) => (),
-
-            Phase::Neutral => {
-                for (player_i, p) in state.players_mut().into_iter().enumerate() {
-                    if p.in_fast_move && p.dur_turns != 0 {  // 硬直中
-                        // 硬直中に交代やスペシャルアタックをすると、硬直後に実行される。
-                        // 硬直中に交代とスペシャルアタックを両方すると、硬直後に代する。
-                        debug_println!("Debug: {}は硬直中", p.poke_name());
+                Action::SwitchPokemon(poke_i) => {
+                    if p.in_fast_move {
+                        p.set_pending(actions[player_i]);
+                    } else if p.switch_pokemon(poke_i.into()) {
+                        events[player_i].push(Event::Switch(p.poke_name()));
+                        action_events.push(BattleEvent::Switch { player_i, poke: p.poke_name(), forced: false });
+                    } else {
+                        action_events.push(BattleEvent::ActionIgnored { player_i, action: actions[player_i] });
                     }
+                },
+
+                Action::FastMove => {
+                    if !p.in_fast_move {  // 硬直中でないなら
+                        p.in_fast_move = true;
+                        p.dur_turns = p.poke().fast_move().turns() - 1;
 
-                    match actions[player_i] {
-                        Action::None => continue,
-
-                        Action::SwitchPokemon(poke_i) => {
-                            if p.in_fast_move {
-                                p.set_pending(actions[player_i]);
-                            } else if p.switch_pokemon(poke_i.into()) {
-                                debug_println!("Debug: 交代 player = {}, 出てきたポケモン = {}", p.name, p.poke_name());
-                            }
-                        },
-
-                        Action::FastMove => {
-                            if !p.in_fast_move {  // 硬直中でないなら
-                                p.in_fast_move = true;
-                                p.dur_turns = p.poke().fast_move().turns() - 1;
-
-                                if p.dur_turns == 0 {
-                                    use_1turn_move[player_i] = true;
-                                }
-
-                                debug_println!("Debug: {}は{}を使った。", p.poke_name(), p.poke().fast_move().name());
-                            }
-                        },
-
-                        Action::ChargeMove(i @ (0 | 1)) => {
-                            if let Some(mv) = p.poke().charge_move(i as usize) {
-                                if p.poke().can_charge_move(i as usize) {
-                                    if p.in_fast_move {
-                                        p.set_pending(actions[player_i]);
-                                    } else {
-                                        use_charge_move[player_i] = i as usize;
-                                    }
-                                } else {
-                                    debug_println!("Debug: [{}] {}に必要なエネルギーが足りない", p.name, mv.name());
-                                }
-                            } else {
-                                debug_println!("Debug: [{}] スペシャルアタック{}が存在しない", p.name, i);
-                            }
-                        },
-
-                        Action::ChargeMove(i) => {
-                            debug_println!("Debug: [{}] 不正な値 Action::ChargeMove({})", p.name, i);
-                        },
+                        if p.dur_turns == 0 {
+                            use_1turn_move[player_i] = true;
+                        }
                     }
-                }
-            },
+                },
+
+                Action::ChargeMove(i) => {
+                    // スペシャルアタックがない、エネルギーが足りない、不正な値なら実行できない
+                    if p.poke().can_charge_move(i as usize) {
+                        if p.in_fast_move {
+                            p.set_pending(actions[player_i]);
+                        } else {
+                            use_charge_move[player_i] = i as usize;
+                        }
+                    } else {
+                        action_events.push(BattleEvent::ActionIgnored { player_i, action: actions[player_i] });
+                    }
+                },
+            }
+        }
+
+        for event in &action_events {
+            obs.on_event(&state, event);
         }
 
         let mut incr_ms = MS_PER_TURN;
-        let switch_strategy = [self.switch0, self.switch1];
-        let shield_strategy = [self.shield0, self.shield1];
+
+        if state.rules.fast_move_timing == FastMoveTiming::Real {
+            state.step_real_timing(use_charge_move, decider, &mut events, obs);
+            return (state, events);
+        }
 
         // ノーマルアタックとスペシャルアタックが同時に使われた場合は、
         // ノーマルアタックが有効になることも、無効になることもあるらしい。
@@ -275,39 +718,55 @@ impl Battle {
 
         // １ターン技はランダムに先行が決まる仕様にしている
         // 本物は先に打った方が先行になる
-        state.do_1turn_move(use_1turn_move);
-        incr_ms += state.switch_if_faint(switch_strategy);
+        state.do_1turn_move(use_1turn_move, decider, &mut events, obs);
+        incr_ms += state.switch_if_faint(decider, &mut events, obs);
 
         // スペシャルアタック。switch_if_faintを含む
-        incr_ms += state.do_charge_move(use_charge_move, shield_strategy, switch_strategy);
+        incr_ms += state.do_charge_move(use_charge_move, decider, &mut events, obs);
 
         // スペシャルアタックを打った相手が硬直中ならすぐにノーマルアタックできる
-        state.do_fast_move();
-        incr_ms += state.switch_if_faint(switch_strategy);
+        state.do_fast_move(&mut events, obs);
+        incr_ms += state.switch_if_faint(decider, &mut events, obs);
 
-        state.set_gameover_phase();
+        state.set_gameover_phase(obs);
 
-        state.increment_turns(incr_ms);
+        state.increment_turns(incr_ms, obs);
 
-        let ret_val = !matches!(state.phase, Phase::GameOver(_) | Phase::TimeOver(_));
+        (state, events)
+    }
 
-        self.states.push(state);
-        self.actions.push(actions);
+    /// FastMoveTiming::Realでの、行動を決めた後の1ターンの処理
+    fn step_real_timing(&mut self, mut use_charge_move: [usize; 2], decider: &mut dyn Decider, events: &mut [Vec<Event>; 2], obs: &mut dyn BattleObserver) {
+        let mut incr_ms = MS_PER_TURN;
 
-        ret_val
-    }
-}
+        // 相手がスペシャルアタックを使うなら、硬直中のノーマルアタックはその前に終わる
+        for player_i in 0..2 {
+            if use_charge_move[1 - player_i] != 2 {
+                self.player_mut(player_i).dur_turns = 0;
+            }
+        }
 
-pub const CHARGE_MOVE_MS: i32 = 20 * MS_PER_TURN;
-/// ポケモンを倒されてプレイヤーが次のポケモンを選ぶのにかかった時間をこれと仮定
-pub const SWITCH_MS: i32 = 10 * MS_PER_TURN;
+        // このターンに終わるノーマルアタックは同時に入るので、先に瀕死になっても打ったものは当たる
+        self.do_fast_move(events, obs);
 
-impl State {
-    fn elapsed_ms(&self) -> i32 {
-        self.turn * MS_PER_TURN
+        // 瀕死になったポケモンはスペシャルアタックを使えない
+        for (player_i, p) in self.players().into_iter().enumerate() {
+            if p.poke().is_faint() {
+                use_charge_move[player_i] = 2;
+            }
+        }
+
+        incr_ms += self.switch_if_faint(decider, events, obs);
+
+        // スペシャルアタック。switch_if_faintを含む
+        incr_ms += self.do_charge_move(use_charge_move, decider, events, obs);
+
+        self.set_gameover_phase(obs);
+
+        self.increment_turns(incr_ms, obs);
     }
 
-    fn player(&self, i: usize) -> &Player {
+    pub fn player(&self, i: usize) -> &Player {
         if i == 0 {
             &self.player0
         } else {
@@ -315,6 +774,11 @@ impl State {
         }
     }
 
+    /// iの相手のプレイヤーを返す
+    pub fn opponent(&self, i: usize) -> &Player {
+        self.player(1 - i)
+    }
+
     fn player_mut(&mut self, i: usize) -> &mut Player {
         if i == 0 {
             &mut self.player0
@@ -331,53 +795,62 @@ impl State {
         vec![&mut self.player0, &mut self.player1]
     }
 
-    fn do_1turn_move(&mut self, use_1turn_move: [bool; 2]) {
-        let p0 = &mut self.player0;
-        let p1 = &mut self.player1;
+    /// player_iとその相手を返す
+    fn player_and_opponent_mut(&mut self, player_i: usize) -> (&mut Player, &mut Player) {
+        if player_i == 0 {
+            (&mut self.player0, &mut self.player1)
+        } else {
+            (&mut self.player1, &mut self.player0)
+        }
+    }
 
-        if rand::random() {
-            if use_1turn_move[0] && p0.in_fast_move && p0.dur_turns == 0 {
-                p0.team[p0.cur_poke].do_fast_move(&mut p1.team[p1.cur_poke]);
-                p0.in_fast_move = false;
-            }
+    /// player_iのノーマルアタックのダメージを与える
+    fn hit_fast_move(&mut self, player_i: usize, events: &mut [Vec<Event>; 2], obs: &mut dyn BattleObserver) {
+        let opponent_i = 1 - player_i;
+        let rules = self.rules;
+        let (p, o) = self.player_and_opponent_mut(player_i);
+        let was_faint = o.poke().is_faint();
 
-            let is_faint = p1.team[p1.cur_poke].is_faint();
+        let damage = p.team[p.cur_poke].do_fast_move(&mut o.team[o.cur_poke], &rules);
+        p.in_fast_move = false;
 
-            if use_1turn_move[1] && p1.in_fast_move && p1.dur_turns == 0 && !is_faint {
-                p1.team[p1.cur_poke].do_fast_move(&mut p0.team[p0.cur_poke]);
-                p1.in_fast_move = false;
-            }
-        } else {
-            if use_1turn_move[1] && p1.in_fast_move && p1.dur_turns == 0 {
-                p1.team[p1.cur_poke].do_fast_move(&mut p0.team[p0.cur_poke]);
-                p1.in_fast_move = false;
-            }
+        let mv = p.poke().fast_move();
+        let fainted = !was_faint && o.poke().is_faint();
+        let poke = o.poke_name();
 
-            let is_faint = p0.team[p0.cur_poke].is_faint();
+        events[player_i].push(Event::FastMove(mv, damage));
+        obs.on_event(self, &BattleEvent::FastMove { player_i, mv, damage });
 
-            if use_1turn_move[0] && p0.in_fast_move && p0.dur_turns == 0 && !is_faint {
-                p0.team[p0.cur_poke].do_fast_move(&mut p1.team[p1.cur_poke]);
-                p0.in_fast_move = false;
-            }
+        if fainted {
+            events[opponent_i].push(Event::Faint);
+            obs.on_event(self, &BattleEvent::Faint { player_i: opponent_i, poke });
         }
     }
 
-    fn do_fast_move(&mut self) {
-        let p0 = &mut self.player0;
-        let p1 = &mut self.player1;
+    fn do_1turn_move(&mut self, use_1turn_move: [bool; 2], decider: &mut dyn Decider, events: &mut [Vec<Event>; 2], obs: &mut dyn BattleObserver) {
+        // 先攻で結果が変わるのは両方が打つときだけ
+        let order = if use_1turn_move != [true, true] || decider.chance(0.5) { [0, 1] } else { [1, 0] };
+
+        for player_i in order {
+            let p = self.player(player_i);
 
-        if p0.in_fast_move && p0.dur_turns == 0 {
-            p0.team[p0.cur_poke].do_fast_move(&mut p1.team[p1.cur_poke]);
-            p0.in_fast_move = false;
+            if use_1turn_move[player_i] && p.in_fast_move && p.dur_turns == 0 && !p.poke().is_faint() {
+                self.hit_fast_move(player_i, events, obs);
+            }
         }
+    }
 
-        if p1.in_fast_move && p1.dur_turns == 0 {
-            p1.team[p1.cur_poke].do_fast_move(&mut p0.team[p0.cur_poke]);
-            p1.in_fast_move = false;
+    fn do_fast_move(&mut self, events: &mut [Vec<Event>; 2], obs: &mut dyn BattleObserver) {
+        for player_i in 0..2 {
+            let p = self.player(player_i);
+
+            if p.in_fast_move && p.dur_turns == 0 {
+                self.hit_fast_move(player_i, events, obs);
+            }
         }
     }
 
-    fn sort_use_charge_move(&self, mut use_charge_move: [usize; 2]) -> Vec<(usize, usize)> {
+    fn sort_use_charge_move(&self, mut use_charge_move: [usize; 2], decider: &mut dyn Decider, obs: &mut dyn BattleObserver) -> Vec<(usize, usize)> {
         if self.player0.poke().is_faint() {
             use_charge_move[0] = 2;
         }
@@ -394,13 +867,17 @@ impl State {
                 let atk1 = self.player1.poke().stats().attack.floor() as u32;
 
                 if atk0 == atk1 {  // random
-                    if rand::random() {
+                    let first = if decider.chance(0.5) { 0 } else { 1 };
+
+                    if first == 0 {
                         v.push((0, i0));
                         v.push((1, i1));
                     } else {
                         v.push((1, i1));
                         v.push((0, i0));
                     }
+
+                    obs.on_event(self, &BattleEvent::CmpTie { first });
                 } else if atk0 < atk1 {  // player1が先
                     v.push((1, i1));
                     v.push((0, i0));
@@ -424,10 +901,10 @@ impl State {
         v
     }
 
-    fn do_charge_move(&mut self, use_charge_move: [usize; 2], shield_strategy: [fn(&Player) -> bool; 2], switch_strategy: [fn(&Player) -> usize; 2]) -> i32 {
+    fn do_charge_move(&mut self, use_charge_move: [usize; 2], decider: &mut dyn Decider, events: &mut [Vec<Event>; 2], obs: &mut dyn BattleObserver) -> i32 {
         assert!((0..=2).contains(&use_charge_move[0]) && (0..=2).contains(&use_charge_move[1]));
 
-        let v = self.sort_use_charge_move(use_charge_move);
+        let v = self.sort_use_charge_move(use_charge_move, decider, obs);
 
         let mut incr_ms = 0;
 
@@ -435,33 +912,42 @@ impl State {
 
         for (player_i, mv_i) in v {
             let opponent_i = if player_i == 0 { 1 } else { 0 };
-            let shield = num_shields[opponent_i] > 0 && (shield_strategy[opponent_i])(self.player(opponent_i));
+            let mv = self.player(player_i).poke().charge_move(mv_i).unwrap();
+            let shield = num_shields[opponent_i] > 0 && decider.shield(self, opponent_i, mv);
 
-            {
-                let p0 = &mut self.player0;
-                let p1 = &mut self.player1;
-                let poke0 = p0.poke_mut();
-                let poke1 = p1.poke_mut();
+            let (damage, is_buffed) = {
+                let rules = self.rules;
+                let (p, o) = self.player_and_opponent_mut(player_i);
 
-                if player_i == 0 {
-                    poke0.do_charge_move(mv_i, poke1, 1.0, shield);
-                    p1.dur_turns = 0; // CCT(差し込み)
+                let (damage, is_buffed) = p.team[p.cur_poke].do_charge_move(mv_i, &mut o.team[o.cur_poke], &rules, shield, decider);
+                o.dur_turns = 0; // CCT(差し込み)
 
-                    if shield {
-                        p1.num_shields = std::cmp::max(0, p1.num_shields - 1);
-                    }
-                } else {
-                    poke1.do_charge_move(mv_i, poke0, 1.0, shield);
-                    p0.dur_turns = 0; // CCT(差し込み)
-
-                    if shield {
-                        p0.num_shields = std::cmp::max(0, p0.num_shields - 1);
-                    }
+                if shield {
+                    o.num_shields = std::cmp::max(0, o.num_shields - 1);
+                    events[opponent_i].push(Event::Shield);
                 }
+
+                events[player_i].push(Event::ChargeMove(mv, damage, is_buffed));
+                (damage, is_buffed)
+            };
+
+            if shield {
+                obs.on_event(self, &BattleEvent::Shield { player_i: opponent_i });
             }
 
+            obs.on_event(self, &BattleEvent::ChargeMove { player_i, mv, damage, shielded: shield });
+
+            if let (true, Some(buff)) = (is_buffed, mv.buff()) {
+                obs.on_event(self, &BattleEvent::Buff { player_i, mv, buff });
+            }
+
+            // ミニゲームなどの間はターンが進まないので、経過時間にだけ加える
+            self.elapsed_ms += self.rules.charge_move_ms;
+
             if self.player(opponent_i).poke().is_faint() {
-                incr_ms += self.switch_if_faint(switch_strategy);
+                events[opponent_i].push(Event::Faint);
+                obs.on_event(self, &BattleEvent::Faint { player_i: opponent_i, poke: self.player(opponent_i).poke_name() });
+                incr_ms += self.switch_if_faint(decider, events, obs);
                 break;
             }
         }
@@ -470,7 +956,7 @@ impl State {
     }
 
     /// 気絶しているポケモンがいたら、ポケモンを交代させる
-    fn switch_if_faint(&mut self, switch_strategy: [fn(&Player) -> usize; 2]) -> i32 {
+    fn switch_if_faint(&mut self, decider: &mut dyn Decider, events: &mut [Vec<Event>; 2], obs: &mut dyn BattleObserver) -> i32 {
         let mut fainted = vec![];  // 交換が必要なプレイヤーの番号を入れる
 
         for (player_i, p) in self.players_mut().into_iter().enumerate() {
@@ -482,40 +968,38 @@ impl State {
         let mut incr_ms = 0;
 
         for player_i in fainted {
-            let i = (switch_strategy[player_i])(self.player(player_i));
+            let i = decider.switch(self, player_i);
 
-            if !self.player_mut(player_i).switch_pokemon(i) {
+            if !self.player_mut(player_i).replace_fainted(i) {
                 self.player_mut(player_i).force_switch();
             } else {
-                incr_ms = SWITCH_MS;
+                incr_ms = self.rules.switch_ms;
             }
+
+            let poke = self.player(player_i).poke_name();
+            events[player_i].push(Event::Switch(poke));
+            obs.on_event(self, &BattleEvent::Switch { player_i, poke, forced: true });
         }
 
         incr_ms
     }
 
-    fn set_gameover_phase(&mut self) {
+    fn set_gameover_phase(&mut self, obs: &mut dyn BattleObserver) {
         let p0_ended = self.player0.is_ended();
         let p1_ended = self.player1.is_ended();
 
-        match (p0_ended, p1_ended) {
-            (false, true) => {  // プレイヤー0の勝利
-                debug_println!("Debug: Game Over. {}の勝ち", self.player0.name);
-                self.phase = Phase::GameOver(0);
-            },
-            (true, false) => {  // プレイヤー1の勝利
-                debug_println!("Debug: Game Over. {}の勝ち", self.player1.name);
-                self.phase = Phase::GameOver(1);
-            },
-            (true, true) => {  // 引き分け
-                debug_println!("Debug: Game Over. 引き分け");
-                self.phase = Phase::GameOver(2);
-            },
-            (false, false) => (),
-        }
+        let winner = match (p0_ended, p1_ended) {
+            (false, true) => 0,  // プレイヤー0の勝利
+            (true, false) => 1,  // プレイヤー1の勝利
+            (true, true) => 2,  // 引き分け
+            (false, false) => return,
+        };
+
+        self.phase = Phase::GameOver(winner);
+        obs.on_event(self, &BattleEvent::GameOver { winner });
     }
 
-    fn increment_turns(&mut self, mut incr_ms: i32) {
+    fn increment_turns(&mut self, mut incr_ms: i32, obs: &mut dyn BattleObserver) {
         incr_ms = std::cmp::max(MS_PER_TURN, incr_ms);  // 最低でも500msは進める
 
         for i in 0..2 {
@@ -528,24 +1012,65 @@ impl State {
         self.turn += 1;
         self.elapsed_ms += incr_ms;
 
-        if self.turn > LIMIT_TURN {
+        if self.turn > self.rules.limit_turn {
             // のこりHPが多いほうが勝ち
             let hp0 = self.player0.sum_hp();
             let hp1 = self.player1.sum_hp();
 
-            self.phase = match hp0.cmp(&hp1) {
-                Ordering::Less => Phase::TimeOver(1),
-                Ordering::Greater => Phase::TimeOver(0),
-                Ordering::Equal => Phase::TimeOver(2),
+            let winner = match hp0.cmp(&hp1) {
+                Ordering::Less => 1,
+                Ordering::Greater => 0,
+                Ordering::Equal => 2,
             };
 
-            debug_println!("Debug: Time Over");
+            self.phase = Phase::TimeOver(winner);
+            obs.on_event(self, &BattleEvent::TimeOver { winner });
+        }
+    }
+}
+
+/// バトル開始時のプレイヤーの条件。HP、エネルギー、ランク補正は最初に場に出ているポケモンだけに設定する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerOptions {
+    pub num_shields: Option<i32>,  // シールドの数。Noneならルールの数のまま
+    pub energy: i32,  // エネルギー(0～100)
+    pub hp_percent: i32,  // 最大HPに対する残りHPの割合(1～100%)
+    pub buff: (i32, i32),  // ランク補正。(攻撃ランク, 防御ランク)
+    pub switch_ms: i32,  // 交代できるようになるまでのミリ秒
+}
+
+impl Default for PlayerOptions {
+    fn default() -> Self {
+        PlayerOptions {
+            num_shields: None,
+            energy: 0,
+            hp_percent: 100,
+            buff: (0, 0),
+            switch_ms: 0,
+        }
+    }
+}
+
+impl PlayerOptions {
+    /// playerに条件を設定する。範囲外の値は範囲内に収める
+    pub fn apply(&self, player: &mut Player) {
+        if let Some(num_shields) = self.num_shields {
+            player.num_shields = num_shields.max(0);
         }
+
+        player.switch_ms = self.switch_ms.max(0);
+
+        let poke = player.poke_mut();
+        let hp = poke.base_hp() * self.hp_percent.clamp(1, 100) / 100;
+
+        poke.hp = hp.max(1);
+        poke.energy = self.energy.clamp(0, 100);
+        poke.buff = (self.buff.0.clamp(-4, 4), self.buff.1.clamp(-4, 4));
     }
 }
 
 impl Player {
-    fn new(name: String, poke_team: Vec<Pokemon>) -> Self {
+    pub fn new(name: String, poke_team: Vec<Pokemon>) -> Self {
         let team: Vec<_> = poke_team.into_iter().map(|poke| BattlePokemon::new(Arc::new(poke))).collect();
 
         Player {
@@ -630,8 +1155,26 @@ impl Player {
         true
     }
 
+    /// 瀕死になったポケモンの代わりにiを出す。
+    /// 自分から交代するのとは違い、交代できない時間の制限を受けず、新たに制限もかからない。
+    pub fn replace_fainted(&mut self, i: usize) -> bool {
+        if i >= self.team.len() || i == self.cur_poke || self.team[i].hp <= 0 {
+            return false;
+        }
+
+        self.cur_poke = i;
+
+        // リセット
+        self.in_fast_move = false;
+        self.dur_turns = 0;
+        self.pending = Action::None;
+
+        true
+    }
+
     // ポケモンを倒されて、次のポケモンを選ぶ時間が12秒与えられるが、
     // それを過ぎてもポケモンを選ばなかった場合にこちらが選ぶ
+    #[allow(clippy::useless_conversion)]
     pub fn force_switch(&mut self) -> bool {
         // team.len()が3なら1, 2, 0の順に選ぶ。
         for i in (1..self.team.len()).chain([0].into_iter()) {
@@ -658,6 +1201,7 @@ impl Player {
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_player() {
     let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
     let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
@@ -680,6 +1224,12 @@ fn test_player() {
     p.force_switch();
     assert_eq!(p.poke_name(), "ブラッキー");
 
+    // 瀕死の入れ替えは交代の制限を受けない
+    assert!(p.switch_ms > 0);
+    assert!(!p.switch_pokemon(0));
+    assert!(p.replace_fainted(0));
+    assert_eq!(p.poke_name(), "ココロモリ");
+
     assert_eq!(p.num_remains(), 3);
     assert_eq!(p.is_ended(), false);
 }
@@ -796,8 +1346,8 @@ impl BattlePokemon {
     pub fn add_buff(&mut self, buff_atk: i32, buff_def: i32) -> (i32, i32) {
         let prev = self.buff;
 
-        self.buff = (std::cmp::max(-4, std::cmp::min(self.buff.0 + buff_atk, 4)),
-                     std::cmp::max(-4, std::cmp::min(self.buff.1 + buff_def, 4)));
+        self.buff = ((self.buff.0 + buff_atk).clamp(-4, 4),
+                     (self.buff.1 + buff_def).clamp(-4, 4));
 
         (self.buff.0 - prev.0, self.buff.1 - prev.1)
     }
@@ -825,8 +1375,8 @@ impl BattlePokemon {
         }
     }
 
-    /// ノーマルアタックを実行する
-    pub fn do_fast_move(&mut self, opponent: &mut Self) -> i32 {
+    /// rulesのバトルで、ノーマルアタックでopponentに与えるダメージを計算する
+    pub fn calc_fast_move_damage(&self, opponent: &Self, rules: &BattleRules) -> i32 {
         let mv = self.fast_move();
         let power = mv.real_power(&self.types());  // 威力(タイプ一致を含む)
         let attack = self.stats().attack * rank_mul(self.buff.0);
@@ -839,13 +1389,36 @@ impl BattlePokemon {
             mv.mtype().type_effect_bonus(&opponent.types())
         };
 
-        let damage_m = type_effect * TRAINER_BATTLE_BONUS;
+        let damage_m = type_effect * rules.trainer_battle_bonus;
 
-        let damage = (0.5 * power * (attack / defense) * damage_m).floor() as i32 + 1;
+        (0.5 * power * (attack / defense) * damage_m).floor() as i32 + 1
+    }
 
-        debug_print!("Debug: {} [fast_move {}] 威力 = {:.1}, こうげき = {:.1}, ぼうぎょ = {:.1}",
-                     self.name(), mv.name(), power, attack, defense);
-        debug_println!(", タイプ相性 = {}, ダメージ = {}", type_effect, damage);
+    /// rulesのバトルで、スペシャルアタックmvでopponentに与えるダメージを計算する。シールドは考慮しない。
+    pub fn calc_charge_move_damage(&self, mv: &ChargeMove, opponent: &Self, rules: &BattleRules) -> i32 {
+        let power = mv.real_power(&self.types());  // 威力(タイプ一致を含む)
+        let attack = self.stats().attack * rank_mul(self.buff.0);  // 攻撃ステータス * ステータス変化
+        let defense = opponent.stats().defense * rank_mul(opponent.buff.1);  // 防御ステータス * ステータス変化
+
+        // ダメージ補正 = タイプ相性 * タイプ一致ボーナス(STAB) * トレーナーバトル * スペシャルアタック
+        let type_effect = if self.is_disable_type_effect {  // タイプ相性
+            1.0
+        } else {
+            mv.mtype().type_effect_bonus(&opponent.types())
+        };
+
+        // スペシャルアタックボーナス
+        let cm_bonus = rules.cm_bonus.clamp(0.0, 1.0);
+
+        let damage_m = type_effect * rules.trainer_battle_bonus * cm_bonus;
+
+        (0.5 * power * (attack / defense) * damage_m).floor() as i32 + 1
+    }
+
+    /// ノーマルアタックを実行する
+    pub fn do_fast_move(&mut self, opponent: &mut Self, rules: &BattleRules) -> i32 {
+        let mv = self.fast_move();
+        let damage = self.calc_fast_move_damage(opponent, rules);
 
         opponent.hp = std::cmp::max(opponent.hp - damage, 0);
         self.energy = std::cmp::min(self.energy + mv.energy(), 100);
@@ -854,71 +1427,115 @@ impl BattlePokemon {
     }
 
     /// スペシャルアタックを実行する
-    /// ダメージを返す
-    pub fn do_charge_move(&mut self, i: usize, opponent: &mut Self, mut cm_bonus: f64, shield: bool) -> i32 {
+    /// (ダメージ, ステータス変化が起きたか)を返す
+    pub fn do_charge_move(&mut self, i: usize, opponent: &mut Self, rules: &BattleRules, shield: bool, decider: &mut dyn Decider) -> (i32, bool) {
         let mv = if let Some(mv) = self.charge_move(i) {
             mv
         } else {
-            return 0;
+            return (0, false);
         };
 
         if self.energy < mv.energy() {
-            return 0;
+            return (0, false);
         }
 
-        let damage;
-
-        if shield {
-            damage = 1;
-            debug_println!("Debug: {} [charge_move {}] *** シールド ***", self.name(), mv.name());
+        let damage = if shield {
+            1
         } else {
-            let power = mv.real_power(&self.types());  // 威力(タイプ一致を含む)
-            let attack = self.stats().attack * rank_mul(self.buff.0);  // 攻撃ステータス * ステータス変化
-            let defense = opponent.stats().defense * rank_mul(opponent.buff.1);  // 防御ステータス * ステータス変化
-
-            // ダメージ補正 = タイプ相性 * タイプ一致ボーナス(STAB) * トレーナーバトル * スペシャルアタック
-            let type_effect = if self.is_disable_type_effect {  // タイプ相性
-                1.0
-            } else {
-                mv.mtype().type_effect_bonus(&opponent.types())
-            };
-
-            // スペシャルアタックボーナス
-            if cm_bonus < 0.0 {
-                cm_bonus = 0.0;
-            } else if cm_bonus > 1.0 {
-                cm_bonus = 1.0;
-            }
-
-            let damage_m = type_effect * TRAINER_BATTLE_BONUS * cm_bonus;
-
-            damage = (0.5 * power * (attack / defense) * damage_m).floor() as i32 + 1;
-
-            debug_print!("Debug: {} [charge_move {}] 威力 = {:.1}, こうげき = {:.1}, ぼうぎょ = {:.1}",
-                         self.name(), mv.name(), power, attack, defense);
-            debug_println!(", タイプ相性 = {}, ダメージ = {}", type_effect, damage);
-        }
+            self.calc_charge_move_damage(mv, opponent, rules)
+        };
 
         // ステータス変化
-        if let Some(Buff(you_buff_atk, you_buff_def, opponent_buff_atk, opponent_buff_def)) = mv.buff() {
-            let mut rng = rand::thread_rng();
-            let rand_val = rng.gen::<f32>() * 100.0;
+        let mut is_buffed = false;
 
-            if rand_val < mv.buff_prob() {
+        if let Some(Buff(you_buff_atk, you_buff_def, opponent_buff_atk, opponent_buff_def)) = mv.buff() {
+            if decider.chance(mv.buff_prob() as f64 / 100.0) {
                 self.add_buff(you_buff_atk.into(), you_buff_def.into());
                 opponent.add_buff(opponent_buff_atk.into(), opponent_buff_def.into());
-                debug_println!("Debug: ステータス変化 {:?}", mv.buff());
+                is_buffed = true;
             }
         }
 
         opponent.hp = std::cmp::max(opponent.hp - damage, 0);
         self.energy = std::cmp::max(self.energy - mv.energy(), 0);
 
-        damage
+        (damage, is_buffed)
     }
 }
 
 #[test]
+fn test_battle_rules() {
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let new_battle = |rules: BattleRules| {
+        let mut battle = Battle::new(String::from("p0"), vec![p0.clone(), p2.clone()], String::from("p1"), vec![p1.clone(), p2.clone()]);
+        battle.set_rules(rules);
+        battle
+    };
+
+    let battle = new_battle(BattleRules::by_name("noshield").unwrap());
+    assert_eq!(battle.state().player0.num_shields, 0);
+    assert_eq!(battle.state().player1.team.len(), 2);
+
+    let battle = new_battle(BattleRules::by_name("1v1").unwrap());
+    assert_eq!(battle.state().player1.team.len(), 1);
+    assert!(BattleRules::by_name("unknown").is_none());
+
+    // 制限ターン数を超えると時間切れ
+    let mut battle = new_battle(BattleRules { limit_turn: 10, ..BattleRules::default() });
+    battle.start();
+    assert!(matches!(battle.state().phase, Phase::TimeOver(_)));
+    assert_eq!(battle.logs.len(), 11);
+
+    // ミニゲームの出来が悪いとスペシャルアタックのダメージが減る
+    let damage = |cm_bonus| {
+        let mut state = new_battle(BattleRules { cm_bonus, num_shields: 0, ..BattleRules::default() }).state().clone();
+        state.player0.poke_mut().energy = 100;
+        let mut strategies: [Box<dyn BattleStrategy>; 2] = [Box::new(DefaultStrategy), Box::new(DefaultStrategy)];
+        let mut rng = BattleRng::seed_from_u64(0);
+        let (next, _) = state.step([Action::ChargeMove(0), Action::None], &mut StrategyDecider { strategies: &mut strategies, rng: &mut rng });
+        (state.player1.poke().hp() - next.player1.poke().hp(), next.elapsed_ms - state.elapsed_ms)
+    };
+
+    let (full, ms) = damage(1.0);
+    let (half, _) = damage(0.5);
+    assert!(half < full);
+    assert_eq!(ms, MS_PER_TURN + CHARGE_MOVE_MS);
+}
+
+#[test]
+fn test_player_options() {
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let mut battle = Battle::new(String::from("p0"), vec![p0, p1.clone()], String::from("p1"), vec![p1]);
+    let options = PlayerOptions { num_shields: Some(0), energy: 120, hp_percent: 50, buff: (1, -5), switch_ms: 30000 };
+    battle.set_player_options(0, &options);
+
+    let p = &battle.state().player0;
+    assert_eq!(p.num_shields, 0);
+    assert_eq!(p.switch_ms, 30000);
+    assert_eq!(p.poke().hp(), p.poke().base_hp() / 2);
+    assert_eq!(p.poke().energy, 100);
+    assert_eq!(p.poke().buff, (1, -4));
+
+    // 控えのポケモンはそのまま
+    assert_eq!(p.team[1].hp(), p.team[1].base_hp());
+    assert_eq!(p.team[1].energy, 0);
+
+    // 既定値は何もしていない状態と同じ
+    let before = battle.state().player1.clone();
+    battle.set_player_options(1, &PlayerOptions::default());
+    assert_eq!(battle.state().player1, before);
+
+    battle.start();
+    assert!(battle.winner().is_some());
+}
+
+#[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_battle_pokemon() {
     let koko = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
 
@@ -0,0 +1,193 @@
+//! テーマのあるカップ。リーグのCPとポケモンレベルの上限に加えて、使えるタイプやポケモンを制限する。
+//!
+//! ```toml
+//! name = "ホリデーカップ"
+//! cp = 1500
+//! lv = 50
+//! types = ["ノーマル", "くさ", "こおり", "でんき", "ひこう", "ゴースト"]
+//! banned = ["ブラッキー", "0227"]
+//! allowed = ["マリルリ"]
+//! ```
+//!
+//! cpを省略すると上限なし、lvを省略すると40。lvは個体値ランクなどの計算に使う上限で、持っているポケモンのレベルは制限しない。
+//! typesを省略するとすべてのタイプを使える。
+//! bannedのポケモンは使えない。allowedのポケモンはタイプに関係なく使える(typesを省略すればallowedのポケモンだけ使える)。
+//! ポケモンは図鑑番号か名前で書く。
+
+use std::fmt;
+use std::io::Read;
+
+use anyhow::{Result, bail, anyhow};
+use serde::Deserialize;
+
+use crate::league::{League, LIMIT_LVS};
+use crate::pokepedia::{Pokepedia, pokepedia_by_no, pokepedia_by_name};
+use crate::pokemon::Pokemon;
+use crate::types::{Type, TYPES, TYPE_NAMES};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CupToml {
+    name: String,
+    cp: Option<i32>,
+    lv: Option<f32>,
+    types: Option<Vec<String>>,
+    banned: Option<Vec<String>>,
+    allowed: Option<Vec<String>>,
+}
+
+/// カップ。制限のないカップはリーグと同じ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cup {
+    pub name: String,
+    pub league: League,  // CPの上限と、計算に使うポケモンレベルの上限
+    pub types: Vec<Type>,  // 使えるタイプ。空ならallowedのポケモン以外はタイプで制限しない
+    pub banned: Vec<&'static str>,  // 使えないポケモンの図鑑番号
+    pub allowed: Vec<&'static str>,  // タイプに関係なく使えるポケモンの図鑑番号
+}
+
+impl Cup {
+    /// leagueと同じで、タイプやポケモンの制限がないカップ
+    pub fn open(league: League) -> Self {
+        Cup { name: league.name(), league, types: vec![], banned: vec![], allowed: vec![] }
+    }
+
+    /// タイプやポケモンの制限がないか
+    pub fn is_open(&self) -> bool {
+        self.types.is_empty() && self.banned.is_empty() && self.allowed.is_empty()
+    }
+
+    /// dictの種類のポケモンを使えるか。CPとポケモンレベルは見ない
+    pub fn allows_dict(&self, dict: &Pokepedia) -> bool {
+        self.dict_error(dict.no(), &dict.types()).is_none()
+    }
+
+    /// pokeを使えない理由。使えるならNone。ポケモンレベルの上限は計算用なので見ない
+    pub fn ineligible_reason(&self, poke: &Pokemon) -> Option<String> {
+        if let Some(err) = self.dict_error(poke.no(), &poke.types()) {
+            return Some(err);
+        }
+
+        if !self.league.allows_cp(poke.cp()) {
+            return Some(format!("CP{}を超えている", self.league.limit_cp.unwrap()));
+        }
+
+        None
+    }
+
+    fn dict_error(&self, no: &str, types: &[Type]) -> Option<String> {
+        if self.banned.contains(&no) {
+            return Some(String::from("使えないポケモン"));
+        }
+
+        if self.allowed.contains(&no) {
+            return None;
+        }
+
+        if self.types.is_empty() {
+            // typesがなくてallowedがあれば、allowedのポケモンだけ使える
+            return if self.allowed.is_empty() { None } else { Some(String::from("使えないポケモン")) };
+        }
+
+        if !types.iter().any(|t| self.types.contains(t)) {
+            return Some(String::from("使えないタイプ"));
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for Cup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if self.is_open() {
+            write!(f, "{}", self.league)
+        } else {
+            write!(f, "{} {}", self.name, self.league)
+        }
+    }
+}
+
+/// 図鑑番号か名前からポケモンの図鑑番号を探す
+fn resolve_no(s: &str) -> Result<&'static str> {
+    pokepedia_by_no(s).or_else(|| pokepedia_by_name(s))
+        .map(|dict| dict.no())
+        .ok_or_else(|| anyhow!("存在しないポケモン: {}", s))
+}
+
+/// カップのTOMLを読み込む
+pub fn load_cup<R: Read>(reader: &mut R) -> Result<Cup> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+
+    let data: CupToml = toml::from_str(&contents)?;
+
+    let limit_lv = data.lv.unwrap_or(40.0);
+
+    if !LIMIT_LVS.contains(&limit_lv) {
+        bail!("lvは40, 50, 51のどれか: {}", limit_lv);
+    }
+
+    if let Some(cp) = data.cp {
+        if cp < 10 {
+            bail!("cpが小さすぎる: {}", cp);
+        }
+    }
+
+    let types = data.types.unwrap_or_default().iter().map(|name| {
+        TYPE_NAMES.iter().position(|t| t == name)
+            .map(|i| TYPES[i])
+            .ok_or_else(|| anyhow!("存在しないタイプ: {}", name))
+    }).collect::<Result<Vec<_>>>()?;
+
+    let banned = data.banned.unwrap_or_default().iter().map(|s| resolve_no(s)).collect::<Result<Vec<_>>>()?;
+    let allowed = data.allowed.unwrap_or_default().iter().map(|s| resolve_no(s)).collect::<Result<Vec<_>>>()?;
+
+    Ok(Cup { name: data.name, league: League { limit_cp: data.cp, limit_lv }, types, banned, allowed })
+}
+
+#[test]
+fn test_cup() {
+    let toml = r#"
+        name = "テスト"
+        cp = 1500
+        types = ["みず", "あく"]
+        banned = ["ブラッキー"]
+        allowed = ["0528"]
+    "#;
+
+    let cup = load_cup(&mut toml.as_bytes()).unwrap();
+    assert_eq!(cup.league, League::GREAT);
+    assert_eq!(cup.types, vec![Type::Water, Type::Dark]);
+
+    let namazun = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+    let burakki = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let kokoromori = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+
+    assert!(cup.ineligible_reason(&namazun).is_none());
+    assert_eq!(cup.ineligible_reason(&burakki).as_deref(), Some("使えないポケモン"));
+    assert!(cup.ineligible_reason(&kokoromori).is_none());  // タイプは違うがallowedにいる
+
+    let cup = Cup { allowed: vec![], ..cup };
+    assert_eq!(cup.ineligible_reason(&kokoromori).as_deref(), Some("使えないタイプ"));
+
+    // タイプの制限がなければallowedのポケモンだけ使える
+    let only = Cup { types: vec![], banned: vec![], allowed: vec!["0528"], ..cup.clone() };
+    assert!(only.ineligible_reason(&kokoromori).is_none());
+    assert!(only.ineligible_reason(&namazun).is_some());
+
+    let banned_only = Cup { types: vec![], allowed: vec![], ..cup.clone() };
+    assert!(banned_only.ineligible_reason(&namazun).is_none());
+    assert!(banned_only.ineligible_reason(&burakki).is_some());
+
+    let cup = Cup { league: League::LITTLE, ..cup };
+    assert_eq!(cup.ineligible_reason(&namazun).as_deref(), Some("CP500を超えている"));
+
+    // ポケモンレベルの上限は使えるかどうかに関係ない
+    let xl = Pokemon::new("マリルリ", Some(45.0), (0, 15, 15), "あわ", "じゃれつく", None, 0).unwrap();
+    assert!(xl.cp() <= 1500);
+    assert!(Cup::open(League::GREAT).ineligible_reason(&xl).is_none());
+
+    assert!(load_cup(&mut "name = \"x\"\ntypes = [\"ほげ\"]".as_bytes()).is_err());
+    assert!(load_cup(&mut "name = \"x\"\nbanned = [\"ほげ\"]".as_bytes()).is_err());
+    assert!(load_cup(&mut "name = \"x\"\nlv = 45".as_bytes()).is_err());
+}
@@ -4,6 +4,8 @@ use std::collections::HashMap;
 use std::sync::OnceLock;
 
 use crate::pokepedia::{Pokepedia, pokepedia_by_no};
+#[cfg(test)]
+use crate::pokepedia::pokepedia_by_name;
 
 pub fn evolutions(no: &str) -> Vec<&'static Pokepedia> {
     let m = EVOLUTION_NO_MAP.get_or_init(|| {
@@ -64,6 +66,7 @@ static REV_EVOLUTION_NO_MAP: OnceLock<HashMap<String, Vec<&'static Pokepedia>>>
 pub const NUM_EVOLUTION: usize = 428;
 
 /// 進化
+#[allow(clippy::redundant_static_lifetimes)]
 pub static EVOLUTION: [[&'static str; 2]; NUM_EVOLUTION] = [
     ["0001", "0002"],
     ["0002", "0003"],
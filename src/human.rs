@@ -0,0 +1,185 @@
+//! 人が操作する戦略
+//! 行動できるターンになるたびに入力を求める。シールドを使うかと、瀕死になったときに誰を出すかも聞く。
+
+use std::io::{self, BufRead, Write};
+
+use crate::battle::{State, Action, BattleRng, Player};
+use crate::moves::ChargeMove;
+use crate::strategy::BattleStrategy;
+
+/// 入力を読んで行動を決める戦略。入力が尽きたらノーマルアタックを続け、シールドは使い、交代は自動で選ぶ
+pub struct HumanStrategy {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl HumanStrategy {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self { input, output }
+    }
+
+    /// 標準入力と標準出力を使う
+    pub fn stdio() -> Self {
+        Self::new(Box::new(io::BufReader::new(io::stdin())), Box::new(io::stdout()))
+    }
+
+    /// promptを表示して1行読む。入力が尽きたらNone
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let _ = write!(self.output, "{}", prompt);
+        let _ = self.output.flush();
+
+        let mut line = String::new();
+
+        match self.input.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }
+
+    fn print_status(&mut self, state: &State, player_i: usize) {
+        let p = state.player(player_i);
+        let o = state.opponent(player_i);
+        let poke = p.poke();
+
+        let moves = (0..2).filter_map(|i| poke.charge_move(i).map(|mv| {
+            let ready = if poke.can_charge_move(i) { "*" } else { " " };
+            format!("{}{}: {}({})", ready, i + 1, mv.name(), mv.energy())
+        })).collect::<Vec<_>>();
+
+        let _ = writeln!(self.output, "[{:.1}s] {} HP {}/{} E {} S {} | {} | 相手: {} HP {}/{} E {} S {}",
+                         state.elapsed_ms as f32 / 1000.0, poke.name(), poke.hp(), poke.base_hp(), poke.energy, p.num_shields,
+                         moves.join(" "), o.poke_name(), o.poke().hp(), o.poke().base_hp(), o.poke().energy, o.num_shields);
+    }
+}
+
+/// 交代できるポケモンのインデックス
+fn switch_candidates(p: &Player) -> Vec<usize> {
+    (0..p.team.len()).filter(|i| *i != p.cur_poke && !p.team[*i].is_faint()).collect()
+}
+
+impl BattleStrategy for HumanStrategy {
+    fn name(&self) -> &'static str {
+        "human"
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, _rng: &mut BattleRng) -> Action {
+        let p = state.player(player_i);
+
+        // 硬直中は何を選んでも変わらないので聞かない
+        if p.in_fast_move || p.poke().is_faint() {
+            return Action::FastMove;
+        }
+
+        self.print_status(state, player_i);
+
+        loop {
+            let line = match self.read_line("行動 [Enter/f: ノーマル, 1/2: スペシャル, s<番号>: 交代, w: 待機] > ") {
+                None => return Action::FastMove,
+                Some(line) => line,
+            };
+
+            let action = match line.as_str() {
+                "" | "f" => Action::FastMove,
+                "w" => Action::None,
+                "1" | "2" => {
+                    let i = if line == "1" { 0 } else { 1 };
+
+                    if !p.poke().can_charge_move(i) {
+                        let _ = writeln!(self.output, "エネルギーが足りないか、スペシャルアタック{}がない", i + 1);
+                        continue;
+                    }
+
+                    Action::ChargeMove(i as u8)
+                },
+                s => match s.strip_prefix('s').and_then(|n| n.parse::<usize>().ok()) {
+                    Some(n) if switch_candidates(p).contains(&n.wrapping_sub(1)) && p.switch_ms == 0 => Action::SwitchPokemon((n - 1) as u8),
+                    Some(_) if p.switch_ms > 0 => {
+                        let _ = writeln!(self.output, "あと{:.1}秒交代できない", p.switch_ms as f32 / 1000.0);
+                        continue;
+                    },
+                    _ => {
+                        let team = switch_candidates(p).iter().map(|i| format!("s{}: {}", i + 1, p.team[*i].name())).collect::<Vec<_>>();
+                        let _ = writeln!(self.output, "入力が正しくない。交代先: {}", team.join(", "));
+                        continue;
+                    },
+                },
+            };
+
+            return action;
+        }
+    }
+
+    fn shield(&mut self, state: &State, player_i: usize, mv: &'static ChargeMove, _rng: &mut BattleRng) -> bool {
+        let p = state.player(player_i);
+        let o = state.opponent(player_i);
+        let damage = o.poke().calc_charge_move_damage(mv, p.poke(), &state.rules);
+
+        let prompt = format!("相手の{}の{}! ダメージ{}(HP {}) シールドを使う? 残り{} [Y/n] > ",
+                             o.poke_name(), mv.name(), damage, p.poke().hp(), p.num_shields);
+
+        loop {
+            match self.read_line(&prompt).as_deref() {
+                None | Some("") | Some("y") => return true,
+                Some("n") => return false,
+                _ => continue,
+            }
+        }
+    }
+
+    fn switch(&mut self, state: &State, player_i: usize, _rng: &mut BattleRng) -> usize {
+        let p = state.player(player_i);
+        let candidates = switch_candidates(p);
+
+        if candidates.len() <= 1 {
+            return candidates.first().copied().unwrap_or(0);
+        }
+
+        let team = candidates.iter().map(|i| format!("{}: {}", i + 1, p.team[*i].name())).collect::<Vec<_>>();
+        let prompt = format!("{}が瀕死になった。次に出すポケモン [{}] > ", p.poke_name(), team.join(", "));
+
+        loop {
+            match self.read_line(&prompt) {
+                None => return candidates[0],
+                Some(line) => match line.parse::<usize>() {
+                    Ok(n) if candidates.contains(&n.wrapping_sub(1)) => return n - 1,
+                    _ => continue,
+                },
+            }
+        }
+    }
+}
+
+#[test]
+fn test_human_strategy() {
+    use std::io::Cursor;
+    use rand::SeedableRng;
+    use crate::battle::Battle;
+    use crate::pokemon::Pokemon;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let battle = Battle::new(String::from("p0"), vec![p0, p1, p2.clone()], String::from("p1"), vec![p2]);
+    let mut state = battle.state().clone();
+    let mut rng = BattleRng::seed_from_u64(0);
+
+    // 打てないスペシャルアタックや正しくない入力は聞き直す
+    let input = "1\nx\ns3\nw\n\n";
+    let mut human = HumanStrategy::new(Box::new(Cursor::new(input)), Box::new(io::sink()));
+    assert_eq!(human.action(&state, 0, &mut rng), Action::SwitchPokemon(2));
+    assert_eq!(human.action(&state, 0, &mut rng), Action::None);
+    assert_eq!(human.action(&state, 0, &mut rng), Action::FastMove);
+
+    // 入力が尽きたらノーマルアタック
+    assert_eq!(human.action(&state, 0, &mut rng), Action::FastMove);
+
+    let mv = state.player1.poke().charge_move1();
+    let mut human = HumanStrategy::new(Box::new(Cursor::new("n\n")), Box::new(io::sink()));
+    assert!(!human.shield(&state, 0, mv, &mut rng));
+    assert!(human.shield(&state, 0, mv, &mut rng));
+
+    state.player0.team[0].hp = 0;
+    let mut human = HumanStrategy::new(Box::new(Cursor::new("1\n3\n")), Box::new(io::sink()));
+    assert_eq!(human.switch(&state, 0, &mut rng), 2);
+}
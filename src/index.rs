@@ -1,19 +1,23 @@
 //! CP, SCP, DCPなどのバトルでの強さの参考になる指標を計算する
 
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
 use crate::pokepedia::*;
 use crate::pokemon::IVs;
+use crate::league::League;
 
-/// 引数のlimit_cp以下のCPという条件で、一番高いポケモンレベルを返す。
-/// ポケモンレベル1.0でもlimit_cpを超える場合は、Noneを返す。
-pub fn calc_lv_limited_by_cp(limit_cp: i32, limit_lv: f32, dict: &Pokepedia, ivs: IVs) -> Option<f32> {
-    let to = (limit_lv * 2.0) as usize;
+/// leagueに出せるという条件で、一番高いポケモンレベルを返す。
+/// ポケモンレベル1.0でもleagueのCPの上限を超える場合は、Noneを返す。
+pub fn calc_lv_limited_by_cp(league: League, dict: &Pokepedia, ivs: IVs) -> Option<f32> {
+    let to = (league.limit_lv * 2.0) as usize;
 
     for i in 2..=to {
         let lv = i as f32 / 2.0;
         let stats = dict.base_stats().stats(lv, ivs);
         let cp = stats.calc_cp();
 
-        if cp > limit_cp {
+        if !league.allows_cp(cp) {
             if lv == 1.0 {
                 return None;
             }
@@ -22,31 +26,56 @@ pub fn calc_lv_limited_by_cp(limit_cp: i32, limit_lv: f32, dict: &Pokepedia, ivs
         }
     }
 
-    Some(limit_lv)
+    Some(league.limit_lv)
 }
 
 #[test]
 fn test_calc_lv_limited_by_cp() {
     let kure = pokepedia_by_name("クレセリア").unwrap();
     let ivs = IVs::new(2, 15, 13).unwrap();
-    assert_eq!(calc_lv_limited_by_cp(1500, 50.0, kure, ivs), Some(20.0));
-    assert_eq!(calc_lv_limited_by_cp(5000, 50.0, kure, ivs), Some(50.0));
+    assert_eq!(calc_lv_limited_by_cp(League::GREAT.with_limit_lv(50.0), kure, ivs), Some(20.0));
+    assert_eq!(calc_lv_limited_by_cp(League::MASTER.with_limit_lv(50.0), kure, ivs), Some(50.0));
+    assert_eq!(calc_lv_limited_by_cp(League::MASTER.with_limit_lv(51.0), kure, ivs), Some(51.0));
 
     let hapi = pokepedia_by_name("ハピナス").unwrap();
     let ivs = IVs::new(15, 15, 15).unwrap();
-    assert_eq!(calc_lv_limited_by_cp(39, 40.0, hapi, ivs), Some(1.0));
-    assert_eq!(calc_lv_limited_by_cp(38, 40.0, hapi, ivs), None);
+    assert_eq!(calc_lv_limited_by_cp(League { limit_cp: Some(39), limit_lv: 40.0 }, hapi, ivs), Some(1.0));
+    assert_eq!(calc_lv_limited_by_cp(League { limit_cp: Some(38), limit_lv: 40.0 }, hapi, ivs), None);
 }
 
-/// 一番SCPが高くなる個体値の組み合わせを計算する。
+/// 入手方法ごとの個体値の下限
+pub const IV_FLOORS: [(&str, i32); 7] = [
+    ("wild", 0),  // 野生
+    ("weather", 4),  // 天候ブーストの野生
+    ("friend", 5),  // 親友での交換
+    ("raid", 10),  // レイド
+    ("hatch", 10),  // タマゴ
+    ("research", 10),  // リサーチ
+    ("lucky", 12),  // キラ交換
+];
+
+/// "raid"のような入手方法か0～15の数から個体値の下限を返す
+pub fn iv_floor_by_name(name: &str) -> Option<i32> {
+    match IV_FLOORS.iter().find(|(n, _)| *n == name) {
+        Some((_, floor)) => Some(*floor),
+        None => name.parse().ok().filter(|n| (0..16).contains(n)),
+    }
+}
+
+/// すべての個体値がfloor以上か
+fn is_above_floor(ivs: IVs, floor: i32) -> bool {
+    ivs.attack >= floor && ivs.defense >= floor && ivs.stamina >= floor
+}
+
+/// leagueで一番SCPが高くなる個体値の組み合わせを、すべての個体値がfloor以上の中から計算する。
 /// 戻り値はOption<(SCP, ポケモンレベル, IVs(攻撃個体値, 防御個体値, 耐久個体値))>
-pub fn calc_top_scp_iv_limited_by_cp(limit_cp: i32, limit_lv: f32, dict: &Pokepedia) -> Option<(i32, f32, IVs)> {
+pub fn calc_top_scp_iv_limited_by_cp(league: League, dict: &Pokepedia, floor: i32) -> Option<(i32, f32, IVs)> {
     let mut top_scp = 0;
     let mut top_scp_ivs = None;
 
-    for ivs in (0..(16*16*16)).map(i2ivs) {
+    for ivs in (0..(16*16*16)).map(i2ivs).filter(|ivs| is_above_floor(*ivs, floor)) {
 
-        let lv = calc_lv_limited_by_cp(limit_cp, limit_lv, dict, ivs);
+        let lv = calc_lv_limited_by_cp(league, dict, ivs);
 
         if let Some(lv) = lv {
             let stats = dict.base_stats().stats(lv, ivs);
@@ -66,12 +95,128 @@ pub fn calc_top_scp_iv_limited_by_cp(limit_cp: i32, limit_lv: f32, dict: &Pokepe
 fn test_calc_top_scp_iv_limited_by_cp() {
     let koko = pokepedia_by_name("ココロモリ").unwrap();
     let ivs = IVs::new(0, 15, 9).unwrap();
-    assert_eq!(calc_top_scp_iv_limited_by_cp(1500, 40.0, koko), Some((1476, 38.0, ivs)));
+    assert_eq!(calc_top_scp_iv_limited_by_cp(League::GREAT, koko, 0), Some((1476, 38.0, ivs)));
+
+    // CPの上限がなければポケモンレベルの上限まで上げられて、個体値がすべて最高のときと同じSCPになる
+    let (scp, lv, _) = calc_top_scp_iv_limited_by_cp(League::MASTER.with_limit_lv(50.0), koko, 0).unwrap();
+    assert_eq!(lv, 50.0);
+    assert_eq!(scp, koko.base_stats().stats(50.0, IVs::new(15, 15, 15).unwrap()).calc_scp());
+
+    // レイドの個体値
+    let (_, _, ivs) = calc_top_scp_iv_limited_by_cp(League::GREAT, koko, 10).unwrap();
+    assert!(ivs.attack >= 10 && ivs.defense >= 10 && ivs.stamina >= 10);
+}
+
+/// 個体値ランクの表の1行
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IvRank {
+    pub rank: usize,  // 順位。1から
+    pub ivs: IVs,
+    pub lv: f32,  // リーグのCPの上限以下で一番高いポケモンレベル
+    pub cp: i32,
+    pub scp: i32,
+    pub stat_product: f64,  // ステータス積
+    pub percent: f64,  // 1位に対するステータス積の割合(%)
+}
+
+/// leagueでの個体値ランクの表を作る。すべての個体値の組み合わせを、
+/// CPの上限以下で一番高いポケモンレベルにしたときのステータス積の高い順に並べる。
+/// ポケモンレベル1.0でもCPの上限を超える個体値は含めない。
+/// 順位と割合はすべての個体値の中でのものだが、表にはすべての個体値がfloor以上のもの(入手できるもの)だけを残す。
+pub fn calc_iv_rank_table(league: League, dict: &Pokepedia, floor: i32) -> Vec<IvRank> {
+    let mut v = (0..(16*16*16)).map(i2ivs).filter_map(|ivs| {
+        let lv = calc_lv_limited_by_cp(league, dict, ivs)?;
+        let stats = dict.base_stats().stats(lv, ivs);
+
+        Some(IvRank { rank: 0, ivs, lv, cp: stats.calc_cp(), scp: stats.calc_scp(), stat_product: stats.calc_stat_product(), percent: 0.0 })
+    }).collect::<Vec<_>>();
+
+    v.sort_by(|a, b| b.stat_product.total_cmp(&a.stat_product));
+
+    let top = v.first().map(|r| r.stat_product).unwrap_or(1.0);
+
+    for (i, r) in v.iter_mut().enumerate() {
+        r.rank = i + 1;
+        r.percent = r.stat_product / top * 100.0;
+    }
+
+    v.retain(|r| is_above_floor(r.ivs, floor));
+
+    v
+}
+
+/// 個体値ランクの表のキー(図鑑番号, CPの上限, ポケモンレベルの上限の2倍)
+type IvRankTableKey = (&'static str, Option<i32>, i32);
+
+/// 計算した下限なしの個体値ランクの表
+static IV_RANK_TABLES: OnceLock<Mutex<HashMap<IvRankTableKey, Arc<Vec<IvRank>>>>> = OnceLock::new();
+
+/// 下限なしの個体値ランクの表。一度計算した表は覚えておいて使いまわす
+pub fn iv_rank_table(league: League, dict: &'static Pokepedia) -> Arc<Vec<IvRank>> {
+    let key = (dict.no(), league.limit_cp, (league.limit_lv * 2.0) as i32);
+    let tables = IV_RANK_TABLES.get_or_init(|| Mutex::new(HashMap::new()));
+
+    if let Some(table) = tables.lock().unwrap().get(&key) {
+        return table.clone();
+    }
+
+    let table = Arc::new(calc_iv_rank_table(league, dict, 0));
+    tables.lock().unwrap().insert(key, table.clone());
+
+    table
+}
+
+/// 個体値ランクの表の順位の分布。戻り値のi番目はbounds[i]位以内の個体値の数
+pub fn iv_rank_distribution(table: &[IvRank], bounds: &[usize]) -> Vec<usize> {
+    bounds.iter().map(|bound| table.iter().filter(|r| r.rank <= *bound).count()).collect()
+}
+
+/// 個体値ランクの表からivsの行を探す
+pub fn find_iv_rank(table: &[IvRank], ivs: IVs) -> Option<&IvRank> {
+    table.iter().find(|r| r.ivs == ivs)
+}
+
+#[test]
+fn test_calc_iv_rank_table() {
+    let koko = pokepedia_by_name("ココロモリ").unwrap();
+    let table = calc_iv_rank_table(League::GREAT, koko, 0);
+
+    assert_eq!(table.len(), 4096);
+    assert_eq!(table[0].rank, 1);
+    assert_eq!(table[0].percent, 100.0);
+    assert!(table.windows(2).all(|w| w[0].stat_product >= w[1].stat_product));
+    assert!(table.iter().all(|r| r.cp <= 1500));
+
+    // 1位はSCPが一番高い個体値と同じ
+    let (scp, lv, ivs) = calc_top_scp_iv_limited_by_cp(League::GREAT, koko, 0).unwrap();
+    assert_eq!((table[0].scp, table[0].lv, table[0].ivs), (scp, lv, ivs));
+
+    let r = find_iv_rank(&table, IVs::new(15, 15, 15).unwrap()).unwrap();
+    assert!(r.rank > 1 && r.percent < 100.0);
+
+    // キラ交換では12～15の組み合わせだけが残り、順位は下限なしの表と同じ
+    let lucky = calc_iv_rank_table(League::GREAT, koko, iv_floor_by_name("lucky").unwrap());
+    assert_eq!(lucky.len(), 4 * 4 * 4);
+    assert!(lucky.iter().all(|r| find_iv_rank(&table, r.ivs) == Some(r)));
+    assert_eq!(lucky[0].ivs, calc_top_scp_iv_limited_by_cp(League::GREAT, koko, 12).unwrap().2);
+
+    let dist = iv_rank_distribution(&lucky, &[lucky[0].rank - 1, lucky[0].rank, 4096]);
+    assert_eq!(dist, [0, 1, 64]);
+
+    // 覚えておいた表は同じもの
+    let cached = iv_rank_table(League::GREAT, koko);
+    assert_eq!(*cached, table);
+    assert!(Arc::ptr_eq(&cached, &iv_rank_table(League::GREAT, koko)));
+    assert!(!Arc::ptr_eq(&cached, &iv_rank_table(League::GREAT.with_limit_lv(50.0), koko)));
+
+    assert_eq!(iv_floor_by_name("raid"), Some(10));
+    assert_eq!(iv_floor_by_name("3"), Some(3));
+    assert_eq!(iv_floor_by_name("16"), None);
 }
 
 /// 重複順列を作るための変換。
 /// (0..(16*16*16)).map(i2ivs) で全組み合わせを生成できる
-fn i2ivs(i: usize) -> IVs {
+pub fn i2ivs(i: usize) -> IVs {
     let attack = ((i & 0xF00) >> 8) as i32;
     let defense = ((i & 0xF0) >> 4) as i32;
     let stamina = (i & 0xF) as i32;
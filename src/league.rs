@@ -0,0 +1,104 @@
+//! リーグ(CPとポケモンレベルの上限)
+
+use std::fmt;
+
+/// リーグの名前、表示名、ポケモンレベルの上限が40のリーグ
+const LEAGUES: [(&str, &str, League); 4] = [
+    ("little", "リトルカップ", League::LITTLE),
+    ("great", "スーパーリーグ", League::GREAT),
+    ("ultra", "ハイパーリーグ", League::ULTRA),
+    ("master", "マスターリーグ", League::MASTER),
+];
+
+/// 選べるポケモンレベルの上限(40: XLアメなし, 50: XLアメあり, 51: 最高の相棒)
+pub const LIMIT_LVS: [f32; 3] = [40.0, 50.0, 51.0];
+
+/// リーグ。出せるポケモンのCPとポケモンレベルの上限
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct League {
+    pub limit_cp: Option<i32>,  // CPの上限。Noneなら上限なし
+    pub limit_lv: f32,  // ポケモンレベルの上限
+}
+
+impl League {
+    pub const LITTLE: League = League { limit_cp: Some(500), limit_lv: 40.0 };
+    pub const GREAT: League = League { limit_cp: Some(1500), limit_lv: 40.0 };
+    pub const ULTRA: League = League { limit_cp: Some(2500), limit_lv: 40.0 };
+    pub const MASTER: League = League { limit_cp: None, limit_lv: 40.0 };
+
+    /// ポケモンレベルの上限だけを変えたリーグ
+    pub fn with_limit_lv(self, limit_lv: f32) -> Self {
+        League { limit_lv, ..self }
+    }
+
+    /// cpのポケモンを出せるか
+    pub fn allows_cp(&self, cp: i32) -> bool {
+        self.limit_cp.map(|limit| cp <= limit).unwrap_or(true)
+    }
+
+    /// 表示名。決まったリーグ以外は"CPn以下"
+    pub fn name(&self) -> String {
+        match LEAGUES.iter().find(|(_, _, league)| league.limit_cp == self.limit_cp) {
+            Some((_, name, _)) => name.to_string(),
+            None => format!("CP{}以下", self.limit_cp.unwrap()),
+        }
+    }
+}
+
+impl Default for League {
+    fn default() -> Self {
+        League::GREAT
+    }
+}
+
+impl fmt::Display for League {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.limit_cp {
+            Some(cp) => write!(f, "{}(CP{}, Lv{})", self.name(), cp, self.limit_lv),
+            None => write!(f, "{}(CP上限なし, Lv{})", self.name(), self.limit_lv),
+        }
+    }
+}
+
+/// "great"のようなリーグの名前か、"ultra:50"のように名前とポケモンレベルの上限
+impl std::str::FromStr for League {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, lv) = match s.split_once(':') {
+            None => (s, None),
+            Some((name, lv)) => (name, Some(lv)),
+        };
+
+        let league = match LEAGUES.iter().find(|(key, _, _)| *key == name) {
+            Some((_, _, league)) => *league,
+            None => return Err(format!("存在しないリーグ: {} (little, great, ultra, masterのどれか)", name)),
+        };
+
+        let limit_lv = match lv {
+            None => 40.0,
+            Some(lv) => match lv.parse::<f32>() {
+                Ok(lv) if LIMIT_LVS.contains(&lv) => lv,
+                _ => return Err(format!("ポケモンレベルの上限は40, 50, 51のどれか: {}", lv)),
+            },
+        };
+
+        Ok(league.with_limit_lv(limit_lv))
+    }
+}
+
+#[test]
+fn test_league() {
+    assert_eq!("great".parse(), Ok(League::GREAT));
+    assert_eq!("ultra:50".parse(), Ok(League::ULTRA.with_limit_lv(50.0)));
+    assert_eq!("master:51".parse::<League>().unwrap().limit_cp, None);
+    assert!("great:45".parse::<League>().is_err());
+    assert!("hyper".parse::<League>().is_err());
+
+    assert!(League::LITTLE.allows_cp(500));
+    assert!(!League::LITTLE.allows_cp(501));
+    assert!(League::MASTER.allows_cp(5000));
+
+    assert_eq!(League::GREAT.to_string(), "スーパーリーグ(CP1500, Lv40)");
+    assert_eq!(League { limit_cp: Some(1000), limit_lv: 50.0 }.name(), "CP1000以下");
+}
@@ -3,15 +3,28 @@ mod cpm;
 mod types;
 mod moves;
 mod index;
+mod league;
+mod cup;
+mod appraisal;
 mod pokemon;
 mod battle;
 mod ranking;
 mod evolution;
+mod strategy;
+mod montecarlo;
+mod solver;
+mod scenario;
+mod replay;
+mod timeline;
+mod observer;
+mod human;
+mod net;
 mod utils;
 
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, Write, BufReader};
+use std::net::{TcpListener, TcpStream};
 
 use rustyline::error::ReadlineError;
 use rustyline::{DefaultEditor, Result};
@@ -21,26 +34,55 @@ use crate::pokemon::{Pokemon, IVs, calc_lv, search_near_iv};
 use crate::moves::{FastMove, ChargeMove};
 use crate::types::{NUM_TYPES, TYPE_NAMES, TYPES};
 use crate::evolution::{rev_evolutions, evolutions};
-use crate::utils::{jp_width, jp_fixed_width_string};
-
-const HELP: [[&'static str; 2]; 19] = [
+use crate::battle::{Battle, Phase, State, Action, BattleRules, PlayerOptions, RULES_NAMES};
+use crate::strategy::{StrategyKind, BattleStrategy, OverrideStrategy, strategy_by_name};
+use crate::human::HumanStrategy;
+use crate::observer::PrintObserver;
+use crate::montecarlo::MonteCarloResult;
+use crate::league::League;
+use crate::index::IvRank;
+use crate::cup::Cup;
+use crate::appraisal::Appraisal;
+use crate::ranking::{BattleRanking, SHIELD_SCENARIOS, ROLE_SCENARIOS};
+use crate::solver::{Solver, SolveResult};
+use crate::utils::{jp_width, jp_fixed_width_string, par_map};
+
+const HELP: [[&str; 2]; 37] = [
     ["q, quit", "終了"],
     ["h, help", "ヘルプ"],
-    ["ls", "現在ディレクトリ内のポケモンを一覧表示"],
+    ["ls", "現在ディレクトリ内のポケモンを一覧表示(カップで使えないポケモンには×を付ける)"],
     ["ls dir", "dirディレクトリのポケモンを一覧表示"],
     ["cd dir", "dirディレクトリへ移動"],
     ["mkdir dir", "dirディレクトリを作成"],
-    ["a, add", "現在ディレクトリ内にポケモンを追加"],
-    ["e, edit", "現在ディレクトリ内のポケモンを編集"],
+    ["a, add", "現在ディレクトリ内にポケモンを追加。個体値がわからなければ鑑定結果で入力"],
+    ["e, edit", "現在ディレクトリ内のポケモンを編集。個体値が確定していなければ鑑定結果で絞り込める"],
     ["rm, remove", "現在ディレクトリ内のポケモンを削除"],
     ["save", "変更内容を保存"],
+    ["league [l]", "以降のコマンドで使うリーグを設定し、CPの上限を超えるポケモンを使えなくする(省略すると現在のリーグを表示, l: little, great, ultra, master。\"ultra:50\"のようにポケモンレベルの上限40, 50, 51も指定できる)"],
+    ["cup [file | none]", "TOMLで書いたカップを読み込み、以降のコマンドで使えるポケモンを制限(省略すると現在のカップを表示, none: カップとリーグの制限をなくす)"],
     ["ls_moves", "指定したポケモンの、すべての技の組合せを作成"],
     ["ecp", "指定したポケモンのECPを表示"],
-    ["top_scp", "指定したポケモンでCP1500以下でトップのポケモンを表示"],
+    ["top_scp [league=l] [floor=f]", "指定したポケモンでリーグのSCPがトップのポケモンを表示(f: 個体値の下限)"],
+    ["iv_rank [num] [league=l] [floor=f]", "指定したポケモンのリーグでの個体値ランクの表の上位num行(初期値20)を表示(f: 個体値の下限。wild, weather, friend, raid, hatch, research, luckyか0～15。指定すれば入手できる個体値だけを表示し、順位の分布も表示)"],
     ["top", "ECPの高い技の組合せを表示"],
     ["evo", "指定したポケモンがもし進化したらを表示"],
     ["sim", "sl_trディレクトリのポケモンとのバトルをシミュレーション"],
     ["sim1", "sl_trディレクトリのポケモンとのバトルをシミュレーション"],
+    ["battle [dir0 [dir1]] [s0=ai] [s1=ai] [seed=n] [rules=r] [cm=x] [timing=t] [events=y|n]", "2つのチームでバトルをシミュレーションしてログを表示(events=y: バトル中の出来事も表示, ai: default, fast, random, greedy, shield, bait, smart, r: gbl, noshield, 1v1, x: スペシャルアタックのミニゲームの出来0～1, t: classic, real, 開始時の条件: shields0=n energy0=n hp0=% buff0=攻,防 switch0=秒, 1も同様)"],
+    ["play [dir0 [dir1]] [s1=ai] [seed=n] [rules=r] [cm=x] [timing=t]", "dir0のチームを自分で操作して、dir1のチームのAIとバトル"],
+    ["host port [dir] [ai=name] [seed=n] [rules=r] [bind=addr]", "portで接続を待ち、つないできたjoinの相手とバトル(aiを指定しなければ自分で操作, r: gbl, noshield, 1v1, addr: 待つアドレス。初期値127.0.0.1で同じマシンからしかつなげない。ほかのマシンから受けるなら0.0.0.0)"],
+    ["join host:port [dir] [ai=name]", "hostで待っている相手につないでバトル"],
+    ["mc [dir0 [dir1]] [s0=ai] [s1=ai] [seed=n] [n=num] [rules=r] [cm=x] [timing=t]", "乱数の種を変えてnum回バトルし、勝率などを表示"],
+    ["branch turn [a0=action] [a1=action] [shield0=y|n] [shield1=y|n]", "最後のbattleをturnまで巻き戻し、行動やシールドを変えて続きを比較"],
+    ["timeline [width]", "最後のbattleのHP、エネルギー、出来事の経過をグラフで表示(width: 幅、初期値60)"],
+    ["replay_save file", "最後のbattleを記録ファイルに保存"],
+    ["replay file", "記録ファイルを読み込んで、ターンを移動しながら表示"],
+    ["solve [dir0 [dir1]] [n=turns] [rules=r] [cm=x] [timing=t]", "ゲーム木探索でturnsターン先(初期値60)までの最善の進行と評価値を表示"],
+    ["scenario file [n=turns]", "TOMLで書いたバトルの途中の局面から最善の進行を探索"],
+    ["ranking [num | name] [ai=name] [league=l]", "リーグの全ポケモンの総当たりバトルのランキングを表示(nameならそのポケモンの詳細)"],
+    ["scp_ranking [num] [league=l]", "カップで使えるポケモンを一番SCPが高くなる個体値にしてSCPの上位num匹(初期値30)を表示"],
+    ["roles [dir] [league=l]", "dirディレクトリ(省略すると現在ディレクトリ)のポケモンの役割(lead, switch, closer, attacker)ごとのスコアを表示"],
+    ["ranking_export file [scenario] [league=l]", "ランキング(scenarioを指定すればバトルレーティングの表)をCSVで書き出す"],
     ["effect", "相性表を表示"],
     ["ecross", "相性の対称性を表示"],
 ];
@@ -50,6 +92,10 @@ fn main() -> Result<()> {
 
     let (mut pdir, mut changed_pdir) = load_pokemons();
 
+    let mut last_battle: Option<(Battle, [StrategyKind; 2])> = None;  // 最後にbattleコマンドで行ったバトル
+    let mut battle_ranking: Option<BattleRanking> = None;  // 計算に時間がかかるので一度計算したら使いまわす
+    let mut cup: Option<Cup> = None;  // 使えるポケモンを制限するカップ。Noneなら制限しない
+
     let mut rl = DefaultEditor::new()?;
 
     loop {
@@ -103,12 +149,12 @@ fn main() -> Result<()> {
                                     println!("{}", s);
                                 }
                             } else if pdir.contains_key(&name) {
-                                ls_print(pdir.get(&name).unwrap());
+                                ls_print(pdir.get(&name).unwrap(), cup.as_ref());
                             } else {
                                 eprintln!("存在しないディレクトリ: {}", name);
                             }
                         } else {
-                            ls_print(pdir.get(&cd).unwrap());
+                            ls_print(pdir.get(&cd).unwrap(), cup.as_ref());
                         }
                     },
 
@@ -183,9 +229,54 @@ fn main() -> Result<()> {
                         }
                     },
 
+                    "league" => {
+                        match words.get(1).map(|s| s.parse::<League>()) {
+                            None => match &cup {
+                                None => println!("リーグの制限なし(計算には{}を使う)", League::default()),
+                                Some(cup) => println!("{}", cup.league),
+                            },
+                            Some(Ok(league)) => {
+                                // カップの制限はそのままでリーグだけ変える
+                                match &mut cup {
+                                    None => cup = Some(Cup::open(league)),
+                                    Some(cup) => cup.league = league,
+                                }
+                                println!("リーグを{}にしました。", league);
+                            },
+                            Some(Err(err)) => eprintln!("{}", err),
+                        }
+                    },
+
+                    "cup" => {
+                        match words.get(1) {
+                            None => match &cup {
+                                None => println!("カップの制限なし"),
+                                Some(cup) => print_cup(cup),
+                            },
+                            Some(&"none") => {
+                                cup = None;
+                                println!("カップとリーグの制限をなくしました。");
+                            },
+                            Some(file) => {
+                                match File::open(file).map_err(anyhow::Error::from).and_then(|mut f| cup::load_cup(&mut f)) {
+                                    Ok(c) => {
+                                        print_cup(&c);
+                                        cup = Some(c);
+                                    },
+                                    Err(err) => eprintln!("カップを読み込めない: {}", err),
+                                }
+                            },
+                        }
+                    },
+
                     "ls_moves2" => {
+                        let (cup, _) = match take_cup(&words[1..], cup.as_ref()) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
                         if let Some(dict) = pokepedia::skim_pokepedia() {
-                            if let Some((_, lv, ivs)) = crate::index::calc_top_scp_iv_limited_by_cp(1500, 40.0, dict) {
+                            if let Some((_, lv, ivs)) = crate::index::calc_top_scp_iv_limited_by_cp(cup.league, dict, 0) {
                                 let p = Pokemon::raw_new(dict, lv, ivs, dict.fast_moves()[0], dict.charge_moves()[0], None);
                                 ls_moves(&p.move_perm());
                             }
@@ -200,8 +291,23 @@ fn main() -> Result<()> {
                     },
 
                     "top_scp" => {
+                        let (cup, args) = match take_cup(&words[1..], cup.as_ref()) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        let floor = match take_iv_floor(&args) {
+                            Some((floor, _)) => floor.unwrap_or(0),
+                            None => continue,
+                        };
+
                         if let Some(dict) = pokepedia::skim_pokepedia() {
-                            if let Some((_, lv, ivs)) = crate::index::calc_top_scp_iv_limited_by_cp(1500, 40.0, dict) {
+                            if !cup.allows_dict(dict) {
+                                eprintln!("{}は{}で使えない", dict.name(), cup.name);
+                                continue;
+                            }
+
+                            if let Some((_, lv, ivs)) = crate::index::calc_top_scp_iv_limited_by_cp(cup.league, dict, floor) {
                                 let p = Pokemon::raw_new(dict, lv, ivs, dict.fast_moves()[0], dict.charge_moves()[0], None);
                                 println!("{}", p.format(jp_width(dict.name())));
 
@@ -223,25 +329,59 @@ fn main() -> Result<()> {
                         };
                     },
 
+                    "iv_rank" => {
+                        let (cup, args) = match take_cup(&words[1..], cup.as_ref()) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        let (floor, args) = match take_iv_floor(&args) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        let num = match args.first().map(|s| s.parse::<usize>()) {
+                            None => 20,
+                            Some(Ok(num)) => num,
+                            Some(Err(_)) => {
+                                eprintln!("Usage: iv_rank [num] [league=l] [floor=f]");
+                                continue;
+                            },
+                        };
+
+                        if let Some(dict) = pokepedia::skim_pokepedia() {
+                            let table = index::calc_iv_rank_table(cup.league, dict, floor.unwrap_or(0));
+                            println!("{} {}", dict.name(), cup.league);
+                            print_iv_rank_table(&table, num);
+
+                            if let Some(floor) = floor {
+                                print_iv_rank_distribution(&table, floor);
+                            }
+                        }
+                    },
+
                     "top" => {
-                        let pokemons = pdir.get(&cd).unwrap();
-                        top_ecp(pokemons);
+                        let pokemons = eligible_pokemons(pdir.get(&cd).unwrap(), cup.as_ref());
+                        top_ecp(&pokemons);
                     },
 
                     "evo" => {
                         let pokemons = pdir.get(&cd).unwrap();
                         if let Some(poke) = select_pokemon(pokemons) {
+                            // 個体値が確定していなければ、候補それぞれを進化させて範囲で表示する
+                            let candidates = poke.candidate_pokemons();
+                            let evolve = |dict: &'static Pokepedia| candidates.iter()
+                                .map(|c| Pokemon::raw_new(dict, c.lv(), c.ivs(), c.fast_move(), c.charge_move1(), c.charge_move2()))
+                                .collect::<Vec<_>>();
+
                             for next_dict in evolutions(poke.no()) {
-                                let p = Pokemon::raw_new(next_dict, poke.lv(), poke.ivs(), poke.fast_move(), poke.charge_move1(), poke.charge_move2());
-                                println!("{}", p.format(jp_width(next_dict.name())));
+                                println!("{}", pokemon::format_candidates(&evolve(next_dict), jp_width(next_dict.name())));
 
                                 for nn_dict in evolutions(next_dict.no()) {
-                                    let p = Pokemon::raw_new(nn_dict, poke.lv(), poke.ivs(), poke.fast_move(), poke.charge_move1(), poke.charge_move2());
-                                    println!("{}", p.format(jp_width(nn_dict.name())));
+                                    println!("{}", pokemon::format_candidates(&evolve(nn_dict), jp_width(nn_dict.name())));
 
                                     for nnn_dict in evolutions(nn_dict.no()) {
-                                        let p = Pokemon::raw_new(nnn_dict, poke.lv(), poke.ivs(), poke.fast_move(), poke.charge_move1(), poke.charge_move2());
-                                        println!("{}", p.format(jp_width(nnn_dict.name())));
+                                        println!("{}", pokemon::format_candidates(&evolve(nnn_dict), jp_width(nnn_dict.name())));
                                     }
                                 }
                             }
@@ -249,8 +389,8 @@ fn main() -> Result<()> {
                     },
 
                     "sim" => {
-                        let pokemons = pdir.get(&cd).unwrap();
-                        let opponents = pdir.get("sl_tr").unwrap();
+                        let pokemons = &eligible_pokemons(pdir.get(&cd).unwrap(), cup.as_ref()).into_iter().filter(is_ivs_fixed).collect::<Vec<_>>();
+                        let opponents = &eligible_pokemons(pdir.get("sl_tr").unwrap(), cup.as_ref()).into_iter().filter(is_ivs_fixed).collect::<Vec<_>>();
                         let width = pokemons.iter().map(|p| jp_width(p.name())).max();
                         let len = opponents.len();
 
@@ -282,10 +422,10 @@ fn main() -> Result<()> {
 
                     "sim1" => {
                         let pokemons = pdir.get(&cd).unwrap();
-                        if let Some(poke) = select_pokemon(pokemons) {
+                        if let Some(poke) = select_pokemon(pokemons).filter(|p| is_ivs_fixed(p)) {
                             println!("{}", poke.format(jp_width(poke.name())));
 
-                            let opponents = pdir.get("sl_tr").unwrap();
+                            let opponents = &eligible_pokemons(pdir.get("sl_tr").unwrap(), cup.as_ref()).into_iter().filter(is_ivs_fixed).collect::<Vec<_>>();
                             let width = opponents.iter().map(|p| jp_width(p.name())).max();
 
                             if let Some(width) = width {
@@ -309,29 +449,7 @@ fn main() -> Result<()> {
                                         }
                                     }
 
-                                    let result;
-
-                                    let r = &ratio[1];
-
-                                    if *r >= 1.3 {
-                                        result = "oooo";
-                                    } else if *r >= 1.2 {
-                                        result = "ooo ";
-                                    } else if *r >= 1.1 {
-                                        result = "oo  ";
-                                    } else if *r >= 1.03 {
-                                        result = "o   ";
-                                    } else if *r >= 0.97 {
-                                        result = "-   ";
-                                    } else if *r >= 0.9 {
-                                        result = "x   ";
-                                    } else if *r >= 0.8 {
-                                        result = "xx  ";
-                                    } else if *r >= 0.7 {
-                                        result = "xxx ";
-                                    } else {
-                                        result = "xxxx";
-                                    }
+                                    let result = ratio_to_rank(ratio[1]);
 
                                     println!("{} {} [2] {}/{} = {:.2} [1] {}/{} = {:.2} [0] {}/{} = {:.2}", result, name,
                                              turns1[2], turns0[2], ratio[2],
@@ -344,6 +462,397 @@ fn main() -> Result<()> {
                         }
                     },
 
+                    "battle" => {
+                        let args = match parse_battle_args(&words[1..], &[]) {
+                            None => {
+                                eprintln!("Usage: battle [dir0 [dir1]] [s0=ai] [s1=ai] [seed=n] [rules=gbl|noshield|1v1] [cm=0～1] [timing=classic|real] [events=y|n]");
+                                continue;
+                            },
+                            Some(v) => v,
+                        };
+
+                        let team0 = select_team(&pdir, cup.as_ref(), args.dirs.first().copied(), &cd, args.rules.team_size);
+                        let team1 = team0.as_ref().and_then(|_| select_team(&pdir, cup.as_ref(), args.dirs.get(1).copied(), &cd, args.rules.team_size));
+
+                        if let (Some((name0, team0)), Some((name1, team1))) = (team0, team1) {
+                            let mut battle = Battle::new(name0, team0, name1, team1);
+                            battle.set_strategy(0, args.kinds[0].create());
+                            battle.set_strategy(1, args.kinds[1].create());
+                            battle.set_rules(args.rules);
+                            battle.set_player_options(0, &args.options[0]);
+                            battle.set_player_options(1, &args.options[1]);
+                            if let Some(seed) = args.seed {
+                                battle.set_seed(seed);
+                            }
+                            if args.events {
+                                battle.add_observer(Box::new(PrintObserver::new(io::stdout())));
+                            }
+                            battle.start();
+                            print_battle(&battle);
+                            last_battle = Some((battle, args.kinds));
+                        }
+                    },
+
+                    "play" => {
+                        let args = match parse_battle_args(&words[1..], &["s0", "n", "events"]) {
+                            None => {
+                                eprintln!("Usage: play [dir0 [dir1]] [s1=ai] [seed=n] [rules=gbl|noshield|1v1] [cm=0～1] [timing=classic|real]");
+                                continue;
+                            },
+                            Some(v) => v,
+                        };
+
+                        let team0 = select_team(&pdir, cup.as_ref(), args.dirs.first().copied(), &cd, args.rules.team_size);
+                        let team1 = team0.as_ref().and_then(|_| select_team(&pdir, cup.as_ref(), args.dirs.get(1).copied(), &cd, args.rules.team_size));
+
+                        if let (Some((name0, team0)), Some((name1, team1))) = (team0, team1) {
+                            let mut battle = Battle::new(name0, team0, name1, team1);
+                            battle.set_strategy(0, Box::new(HumanStrategy::stdio()));
+                            battle.set_strategy(1, args.kinds[1].create());
+                            battle.set_rules(args.rules);
+                            battle.set_player_options(0, &args.options[0]);
+                            battle.set_player_options(1, &args.options[1]);
+                            if let Some(seed) = args.seed {
+                                battle.set_seed(seed);
+                            }
+                            play_battle(&mut battle);
+                        }
+                    },
+
+                    "host" => {
+                        let (port, args) = match words.get(1).and_then(|s| s.parse::<u16>().ok()).zip(parse_net_args(&words[2..], &[])) {
+                            None => {
+                                eprintln!("Usage: host port [dir] [ai=name] [seed=n] [rules=gbl|noshield|1v1] [bind=addr]");
+                                continue;
+                            },
+                            Some(v) => v,
+                        };
+
+                        if let Some(team) = select_team(&pdir, cup.as_ref(), args.dir, &cd, args.rules.team_size) {
+                            let listener = match TcpListener::bind((args.bind, port)) {
+                                Ok(listener) => listener,
+                                Err(err) => {
+                                    eprintln!("{}:{}で待てない: {}", args.bind, port, err);
+                                    continue;
+                                },
+                            };
+
+                            println!("{}:{}で接続を待っています...", args.bind, port);
+
+                            let result = listener.accept().map_err(anyhow::Error::from).and_then(|(stream, addr)| {
+                                println!("{}から接続", addr);
+                                net::host(stream, team, cup.as_ref(), args.strategy(), args.rules, args.seed, &mut io::stdout())
+                            });
+
+                            match result {
+                                Ok(battle) => println!("{}", result_string(battle.state())),
+                                Err(err) => eprintln!("バトルを中断: {}", err),
+                            }
+                        }
+                    },
+
+                    "join" => {
+                        let (addr, args) = match words.get(1).zip(parse_net_args(&words[2..], &["seed", "rules", "bind"])) {
+                            None => {
+                                eprintln!("Usage: join host:port [dir] [ai=name]");
+                                continue;
+                            },
+                            Some(v) => v,
+                        };
+
+                        if let Some((name, team)) = select_team(&pdir, cup.as_ref(), args.dir, &cd, args.rules.team_size) {
+                            let result = TcpStream::connect(addr).map_err(anyhow::Error::from).and_then(|stream| {
+                                net::join(stream, &name, &team, args.strategy().as_mut(), &mut io::stdout())
+                            });
+
+                            match result {
+                                Ok(state) => println!("{}", result_string(&state)),
+                                Err(err) => eprintln!("バトルを中断: {}", err),
+                            }
+                        }
+                    },
+
+                    "branch" => {
+                        let (battle, kinds) = match &last_battle {
+                            Some(v) => v,
+                            None => {
+                                eprintln!("先にbattleコマンドでバトルしてください。");
+                                continue;
+                            },
+                        };
+
+                        let args = match parse_branch_args(&words[1..]) {
+                            Some(args) if (1..=battle.actions.len()).contains(&args.turn) => args,
+                            _ => {
+                                eprintln!("Usage: branch turn [a0=action] [a1=action] [shield0=y|n] [shield1=y|n] (turn: 1～{}, action: fast, cm1, cm2, sw0～sw2)",
+                                          battle.actions.len());
+                                continue;
+                            },
+                        };
+
+                        // 指定したターンの行動を決める直前の状態から分岐する
+                        let i = args.turn - 1;
+                        let turn = battle.states[i].turn;
+                        let strategies: [Box<dyn BattleStrategy>; 2] = [0, 1].map(|player_i| {
+                            Box::new(OverrideStrategy::new(kinds[player_i].create(), turn, args.actions[player_i], args.shields[player_i]))
+                                as Box<dyn BattleStrategy>
+                        });
+
+                        let mut branch = battle.branch(i, strategies);
+                        branch.start();
+                        print_branch(battle, &branch, i);
+                    },
+
+                    "mc" => {
+                        let args = match parse_battle_args(&words[1..], &["events"]) {
+                            None => {
+                                eprintln!("Usage: mc [dir0 [dir1]] [s0=ai] [s1=ai] [seed=n] [n=num_battles] [rules=gbl|noshield|1v1] [cm=0～1] [timing=classic|real]");
+                                continue;
+                            },
+                            Some(v) => v,
+                        };
+
+                        let team0 = select_team(&pdir, cup.as_ref(), args.dirs.first().copied(), &cd, args.rules.team_size);
+                        let team1 = team0.as_ref().and_then(|_| select_team(&pdir, cup.as_ref(), args.dirs.get(1).copied(), &cd, args.rules.team_size));
+
+                        if let (Some((name0, team0)), Some((name1, team1))) = (team0, team1) {
+                            let seed = args.seed.unwrap_or_else(rand::random);
+                            let num_battles = args.num.unwrap_or(1000);
+                            let result = montecarlo::run(&team0, &team1, args.kinds, args.rules, args.options, num_battles, seed);
+
+                            println!("{}({}) vs {}({}), seed = {}", name0, args.kinds[0].name(), name1, args.kinds[1].name(), seed);
+                            print_monte_carlo(&result, [&name0, &name1]);
+                        }
+                    },
+
+                    "timeline" => {
+                        let battle = match &last_battle {
+                            Some((battle, _)) => battle,
+                            None => {
+                                eprintln!("先にbattleコマンドでバトルしてください。");
+                                continue;
+                            },
+                        };
+
+                        let num_cols = match words.get(1).map(|s| s.parse::<usize>()) {
+                            None => 60,
+                            Some(Ok(n)) if n > 0 => n,
+                            _ => {
+                                eprintln!("Usage: timeline [width]");
+                                continue;
+                            },
+                        };
+
+                        for line in timeline::format_timeline(battle, num_cols) {
+                            println!("{}", line);
+                        }
+                    },
+
+                    "replay_save" => {
+                        let battle = match (&last_battle, words.len()) {
+                            (Some((battle, _)), 2) => battle,
+                            (None, _) => {
+                                eprintln!("先にbattleコマンドでバトルしてください。");
+                                continue;
+                            },
+                            _ => {
+                                eprintln!("Usage: replay_save file");
+                                continue;
+                            },
+                        };
+
+                        let res = File::create(words[1]).map_err(anyhow::Error::from)
+                            .and_then(|mut writer| replay::save_replay(&mut writer, battle));
+
+                        match res {
+                            Ok(_) => println!("{}に保存しました。", words[1]),
+                            Err(err) => eprintln!("保存に失敗: {}", err),
+                        }
+                    },
+
+                    "replay" => {
+                        if words.len() != 2 {
+                            eprintln!("Usage: replay file");
+                            continue;
+                        }
+
+                        let battle = File::open(words[1]).map_err(anyhow::Error::from)
+                            .and_then(|mut reader| replay::load_replay(&mut reader));
+
+                        match battle {
+                            Ok(battle) => {
+                                view_battle(&mut rl, &battle);
+
+                                // 読み込んだバトルもbranchで分岐できるようにする
+                                let kinds = [0, 1].map(|i| strategy_by_name(battle.strategies[i].name()).unwrap());
+                                last_battle = Some((battle, kinds));
+                            },
+                            Err(err) => eprintln!("読み込みに失敗: {}", err),
+                        }
+                    },
+
+                    "solve" => {
+                        let args = match parse_battle_args(&words[1..], &["s0", "s1", "seed", "events"]) {
+                            None => {
+                                eprintln!("Usage: solve [dir0 [dir1]] [n=turns] [rules=gbl|noshield|1v1] [cm=0～1] [timing=classic|real]");
+                                continue;
+                            },
+                            Some(v) => v,
+                        };
+
+                        let team0 = select_team(&pdir, cup.as_ref(), args.dirs.first().copied(), &cd, args.rules.team_size);
+                        let team1 = team0.as_ref().and_then(|_| select_team(&pdir, cup.as_ref(), args.dirs.get(1).copied(), &cd, args.rules.team_size));
+
+                        if let (Some((name0, team0)), Some((name1, team1))) = (team0, team1) {
+                            let mut battle = Battle::new(name0, team0, name1, team1);
+                            battle.set_rules(args.rules);
+                            battle.set_player_options(0, &args.options[0]);
+                            battle.set_player_options(1, &args.options[1]);
+                            let mut solver = Solver::new(args.num.unwrap_or(60) as i32);
+                            let result = solver.solve(battle.state());
+                            print_solve_result(battle.state(), &result, solver.max_turns);
+                        }
+                    },
+
+                    "scenario" => {
+                        let args = match parse_battle_args(&words[1..], &[]) {
+                            Some(args) if args.dirs.len() == 1 => args,
+                            _ => {
+                                eprintln!("Usage: scenario file [n=turns]");
+                                continue;
+                            },
+                        };
+
+                        let state = File::open(args.dirs[0]).map_err(anyhow::Error::from)
+                            .and_then(|mut reader| scenario::load_scenario(&mut reader));
+
+                        match state {
+                            Ok(state) => {
+                                let mut solver = Solver::new(args.num.unwrap_or(60) as i32);
+                                let result = solver.solve(&state);
+                                print_solve_result(&state, &result, solver.max_turns);
+                            },
+                            Err(err) => eprintln!("シナリオを読み込めない: {}", err),
+                        }
+                    },
+
+                    "ranking" => {
+                        let (cup, args) = match take_cup(&words[1..], cup.as_ref()) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        let (kind, args) = match take_ai(&args) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        let target = args.first().copied();
+
+                        let ranking = get_battle_ranking(&mut battle_ranking, cup, kind);
+
+                        match target {
+                            None => print_battle_ranking(ranking, 30),
+                            Some(arg) => {
+                                if let Ok(num) = arg.parse() {
+                                    print_battle_ranking(ranking, num);
+                                } else if let Some(i) = ranking.find(arg) {
+                                    print_battle_ranking_detail(ranking, i);
+                                } else {
+                                    eprintln!("ランキングにいないポケモン: {}", arg);
+                                }
+                            },
+                        }
+                    },
+
+                    "scp_ranking" => {
+                        let (cup, args) = match take_cup(&words[1..], cup.as_ref()) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        let num = match args.first().map(|s| s.parse::<usize>()) {
+                            None => 30,
+                            Some(Ok(num)) => num,
+                            Some(Err(_)) => {
+                                eprintln!("Usage: scp_ranking [num] [league=l]");
+                                continue;
+                            },
+                        };
+
+                        let v = ranking::scp_ranking(&cup);
+                        let width = v.iter().take(num).map(|p| jp_width(p.name())).max().unwrap_or(0);
+
+                        println!("{}", cup);
+                        for (i, p) in v.iter().take(num).enumerate() {
+                            println!("{:>4} {}", i + 1, p.format(width));
+                        }
+                    },
+
+                    "roles" => {
+                        let (cup, args) = match take_cup(&words[1..], cup.as_ref()) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        let pokemons = match args.first() {
+                            None => pdir.get(&cd).unwrap(),
+                            Some(dir) => match pdir.get(*dir) {
+                                Some(pokemons) => pokemons,
+                                None => {
+                                    eprintln!("存在しないディレクトリ: {}", dir);
+                                    continue;
+                                },
+                            },
+                        };
+
+                        let pokemons = eligible_pokemons(pokemons, Some(&cup)).into_iter().filter(is_ivs_fixed).collect::<Vec<_>>();
+                        // 対戦相手にはカップのポケモンを使う。ランキングを計算済みならそのポケモンと戦略を使い、総当たりはしない
+                        let (opponents, kind) = match &battle_ranking {
+                            Some(r) if r.cup == cup => (r.pokemons.clone(), r.kind),
+                            _ => (ranking::cup_pokemons(&cup), StrategyKind::Default),
+                        };
+
+                        print_role_scores(&opponents, kind, &pokemons);
+                    },
+
+                    "ranking_export" => {
+                        let (cup, words) = match take_cup(&words, cup.as_ref()) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+
+                        let scenario_i = match words.get(2).map(|s| s.parse::<usize>()) {
+                            None => None,
+                            Some(Ok(i)) if i < SHIELD_SCENARIOS.len() => Some(i),
+                            _ => {
+                                eprintln!("シナリオは0から{}の値で指定してください。", SHIELD_SCENARIOS.len() - 1);
+                                continue;
+                            },
+                        };
+
+                        if words.len() < 2 || words.len() > 3 {
+                            eprintln!("Usage: ranking_export file [scenario]");
+                            continue;
+                        }
+
+                        // 計算済みのランキングがあればその戦略で書き出す
+                        let kind = battle_ranking.as_ref().map(|r| r.kind).unwrap_or(StrategyKind::Default);
+                        let ranking = get_battle_ranking(&mut battle_ranking, cup, kind);
+
+                        let res = File::create(words[1]).map_err(anyhow::Error::from).and_then(|mut writer| {
+                            match scenario_i {
+                                None => ranking.save_csv(&mut writer),
+                                Some(i) => ranking.save_matrix_csv(&mut writer, i),
+                            }
+                        });
+
+                        match res {
+                            Ok(_) => println!("{}に書き出しました。", words[1]),
+                            Err(err) => eprintln!("書き出しに失敗: {}", err),
+                        }
+                    },
+
                     "effect" => {
                         types::Type::print_effect_table(None);
                     },
@@ -396,6 +905,7 @@ fn ratio_to_rank(ratio: f64) -> &'static str {
     }
 }
 
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn load_pokemons() -> (HashMap<String, Vec<Pokemon>>, HashMap<String, bool>) {
     let mut poke_path = dirs::home_dir().unwrap();
     poke_path.push("pokemons");
@@ -421,6 +931,7 @@ fn load_pokemons() -> (HashMap<String, Vec<Pokemon>>, HashMap<String, bool>) {
     (pdir, changed_pdir)
 }
 
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn save_pokemons(pdir: &HashMap<String, Vec<Pokemon>>, changed_pdir: &mut HashMap<String, bool>) {
     let mut poke_path = dirs::home_dir().unwrap();
     poke_path.push("pokemons");
@@ -438,15 +949,123 @@ fn save_pokemons(pdir: &HashMap<String, Vec<Pokemon>>, changed_pdir: &mut HashMa
     }
 }
 
-fn ls_print(pokes: &[Pokemon]) {
+/// pokesを一覧表示する。cupのリーグ(なければスーパーリーグ)での個体値ランクと1位に対する割合も表示し、
+/// cupで使えないポケモンには×と理由を付ける
+fn ls_print(pokes: &[Pokemon], cup: Option<&Cup>) {
     if pokes.is_empty() {
         return;
     }
 
     let width = pokes.iter().map(|p| jp_width(p.name())).max().unwrap();
 
-    for p in pokes {
-        println!("{}", p.format(width));
+    // 個体値ランクの表は同じ種類のポケモンで1つだけ計算する
+    let mut dicts = pokes.iter().map(|p| p.dict()).collect::<Vec<_>>();
+    dicts.sort_by_key(|dict| dict.no());
+    dicts.dedup_by_key(|dict| dict.no());
+
+    let league = cup.map(|cup| cup.league).unwrap_or_default();
+    let tables = par_map(&dicts, |dict| (dict.no(), index::iv_rank_table(league, dict)))
+        .into_iter().collect::<HashMap<_, _>>();
+
+    let ranks = pokes.iter().map(|p| {
+        let table = tables[p.no()].as_slice();
+
+        if p.is_ivs_fixed() {
+            return index::find_iv_rank(table, p.ivs()).map(|r| format!("ランク {:>4} ({:>5.1}%)", r.rank, r.percent));
+        }
+
+        // 個体値が確定していなければ、候補の中での範囲。個体値とSCPの範囲はformatで表示する
+        let rows = p.ivs_candidates().iter().filter_map(|ivs| index::find_iv_rank(table, *ivs)).collect::<Vec<_>>();
+        let best = rows.iter().min_by_key(|r| r.rank)?;
+        let worst = rows.iter().max_by_key(|r| r.rank)?;
+
+        Some(format!("ランク {:>4}～{} ({:.1}～{:.1}%) 候補{}通り",
+                     best.rank, worst.rank, worst.percent, best.percent, p.ivs_candidates().len()))
+    }).collect::<Vec<_>>();
+
+    for (p, rank) in pokes.iter().zip(ranks) {
+        let rank = rank.unwrap_or_else(|| String::from("ランク    - (    -)"));
+
+        match cup.and_then(|cup| cup.ineligible_reason(p)) {
+            None => println!("  {} {}", p.format(width), rank),
+            Some(reason) => println!("× {} {}  ({})", p.format(width), rank, reason),
+        }
+    }
+}
+
+/// 引数から"ai=bait"のような戦略の指定を取り除き、戦略と残りの引数を返す。
+/// 指定がなければDefault。存在しない戦略ならNone
+fn take_ai<'a>(args: &[&'a str]) -> Option<(StrategyKind, Vec<&'a str>)> {
+    let mut kind = StrategyKind::Default;
+    let mut rest = vec![];
+
+    for arg in args {
+        match arg.strip_prefix("ai=") {
+            Some(name) => match strategy_by_name(name) {
+                Some(k) => kind = k,
+                None => {
+                    eprintln!("存在しない戦略: {}", name);
+                    return None;
+                },
+            },
+            None => rest.push(*arg),
+        }
+    }
+
+    Some((kind, rest))
+}
+
+/// 引数から"floor=raid"のような個体値の下限の指定を取り除き、下限と残りの引数を返す。
+/// 指定がなければ下限はNone。正しくなければNone
+fn take_iv_floor<'a>(args: &[&'a str]) -> Option<(Option<i32>, Vec<&'a str>)> {
+    let mut floor = None;
+    let mut rest = vec![];
+
+    for arg in args {
+        match arg.strip_prefix("floor=") {
+            Some(name) => match index::iv_floor_by_name(name) {
+                Some(f) => floor = Some(f),
+                None => {
+                    let names = index::IV_FLOORS.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+                    eprintln!("個体値の下限は{}か0～15: {}", names.join(", "), name);
+                    return None;
+                },
+            },
+            None => rest.push(*arg),
+        }
+    }
+
+    Some((floor, rest))
+}
+
+/// 個体値の下限がfloorのときの、入手できる一番良い個体値と順位の分布を表示する
+fn print_iv_rank_distribution(table: &[IvRank], floor: i32) {
+    let best = match table.first() {
+        None => {
+            println!("個体値{}以上では出せない", floor);
+            return;
+        },
+        Some(r) => r,
+    };
+
+    println!("個体値{}以上: {}通り, 最高 ({}, {}, {}) ランク {} ({:.2}%)", floor, table.len(),
+             best.ivs.attack, best.ivs.defense, best.ivs.stamina, best.rank, best.percent);
+
+    let bounds = [1, 10, 50, 100, 500, 1000, 4096];
+    let dist = index::iv_rank_distribution(table, &bounds);
+
+    for (bound, n) in bounds.iter().zip(dist) {
+        println!("    {:>4}位以内: {:>4} ({:>5.1}%)", bound, n, n as f64 * 100.0 / table.len() as f64);
+    }
+}
+
+/// 個体値ランクの表の上位num行を表示する
+fn print_iv_rank_table(table: &[IvRank], num: usize) {
+    println!("ランク   個体値      Lv     CP  SCP  ステータス積      %");
+
+    for r in table.iter().take(num) {
+        println!("{:>6} ({:>2},{:>2},{:>2}) {:>4.1} {:>6} {:>4} {:>12.0} {:>6.2}",
+                 r.rank, r.ivs.attack, r.ivs.defense, r.ivs.stamina, r.lv, r.cp, r.scp, r.stat_product, r.percent);
     }
 }
 
@@ -455,7 +1074,7 @@ fn ls_moves(pokes: &[Pokemon]) {
         return;
     }
 
-    println!("{} CP {} SCP {}", pokes[0].name(), pokes[0].cp(), pokes[0].scp());
+    println!("{} CP {} SCP {}", pokes[0].name(), pokes[0].cp(), pokes[0].format_scp());
     println!("(PPT, EPT, turns) fast_move,  (PPE, turns) charge_move");
 
     let mut w_fm = 0;
@@ -473,9 +1092,9 @@ fn ls_moves(pokes: &[Pokemon]) {
     sorted.sort_by_key(|p| -p.ecp(None, None, 1));
 
     for p in sorted {
-        let ecp2 = p.ecp(None, None, 2);
-        let ecp1 = p.ecp(None, None, 1);
-        let ecp0 = p.ecp(None, None, 0);
+        let ecp2 = p.format_ecp(None, None, 2);
+        let ecp1 = p.format_ecp(None, None, 1);
+        let ecp0 = p.format_ecp(None, None, 0);
 
         let fm = jp_fixed_width_string(&p.fast_move_desc(), w_fm);
         let cm1 = jp_fixed_width_string(&p.charge_move1_desc(), w_cm1);
@@ -498,7 +1117,7 @@ fn top_ecp(pokes: &[Pokemon]) {
     for p in sorted {
         let name = jp_fixed_width_string(p.name(), width);
 
-        let ecp1 = p.ecp(None, None, 1);
+        let ecp1 = p.format_ecp(None, None, 1);
         let fm = p.fast_move_desc();
         let cm1 = p.charge_move1_desc();
         let cm2 = p.charge_move2_desc();
@@ -507,18 +1126,606 @@ fn top_ecp(pokes: &[Pokemon]) {
         perm.sort_by_key(|p| -p.ecp(None, None, 1));
         let top = &perm[0];
 
-        let top_ecp1 = top.ecp(None, None, 1);
+        let top_ecp1 = top.format_ecp(None, None, 1);
         let top_fm = top.fast_move_desc();
         let top_cm1 = top.charge_move1_desc();
         let top_cm2 = top.charge_move2_desc();
 
-        println!("{} CP {} SCP {}", name, p.cp(), p.scp());
+        println!("{} CP {} SCP {}", name, p.cp(), p.format_scp());
         println!("    cur ECP1 {:>4} {} {} {}", ecp1, fm, cm1, cm2);
         println!("    top ECP1 {:>4} {} {} {}", top_ecp1, top_fm, top_cm1, top_cm2);
         println!();
     }
 }
 
+/// ポケモンの個体値が確定しているか。確定していなければバトルで使う個体値がわからないので、理由を表示してfalseを返す
+fn is_ivs_fixed(poke: &Pokemon) -> bool {
+    if !poke.is_ivs_fixed() {
+        eprintln!("{}は個体値が確定していないので使えない(候補{}通り。editの(i)vsで入力)", poke.name(), poke.ivs_candidates().len());
+    }
+
+    poke.is_ivs_fixed()
+}
+
+/// バトルに出すチームを選ぶ。戻り値は(チーム名, ポケモン)
+/// dirを指定した場合は、dirディレクトリのcupで使えるポケモンの先頭からteam_size匹を選ぶ。
+/// 指定しない場合は、現在ディレクトリからskimで選ぶ。cupで使えないポケモンは選べない。cupがNoneなら制限しない。
+/// 個体値が確定していないポケモンも選べない。
+fn select_team(pdir: &HashMap<String, Vec<Pokemon>>, cup: Option<&Cup>, dir: Option<&str>, cd: &str, team_size: usize) -> Option<(String, Vec<Pokemon>)> {
+    let team: Vec<Pokemon>;
+
+    if let Some(dir) = dir {
+        let pokemons = match pdir.get(dir) {
+            None => {
+                eprintln!("存在しないディレクトリ: {}", dir);
+                return None;
+            },
+            Some(pokemons) => pokemons,
+        };
+
+        team = eligible_pokemons(pokemons, cup).into_iter().filter(is_ivs_fixed).take(team_size).collect();
+    } else {
+        let pokemons = pdir.get(cd).unwrap();
+        let mut v = vec![];
+
+        while v.len() < team_size {
+            match select_pokemon(pokemons) {
+                None => break,
+                Some(poke) => {
+                    if let Some(cup) = cup {
+                        if let Some(reason) = cup.ineligible_reason(poke) {
+                            eprintln!("{}は{}で使えない({})", poke.name(), cup.name, reason);
+                            continue;
+                        }
+                    }
+
+                    if !is_ivs_fixed(poke) {
+                        continue;
+                    }
+
+                    println!("{}匹目: {}", v.len() + 1, poke.name());
+                    v.push(poke.clone());
+                },
+            }
+        }
+
+        team = v;
+    }
+
+    if team.is_empty() {
+        eprintln!("ポケモンが選ばれていない");
+        return None;
+    }
+
+    Some((dir.unwrap_or(cd).to_string(), team))
+}
+
+/// バトル系のコマンドの引数
+struct BattleArgs<'a> {
+    dirs: Vec<&'a str>,  // チームのディレクトリ
+    kinds: [StrategyKind; 2],  // 各プレイヤーの戦略
+    seed: Option<u64>,  // 乱数の種
+    num: Option<usize>,  // バトルの回数
+    rules: BattleRules,  // バトルのルール
+    options: [PlayerOptions; 2],  // 各プレイヤーの開始時の条件
+    events: bool,  // バトル中の出来事を表示する
+}
+
+/// バトル系のコマンドの引数を解析する。
+/// "s0=greedy"のように指定した引数は戦略、"seed=123"は乱数の種、"n=1000"はバトルの回数、
+/// "rules=noshield"はルール、"cm=0.8"はスペシャルアタックのミニゲームの出来、
+/// "timing=real"はノーマルアタックのダメージが入るタイミング、"events=y|n"はバトル中の出来事の表示、それ以外はディレクトリとする。
+/// "shields0=1", "energy1=30", "hp0=50"(%), "buff1=1,-1", "switch0=30"(秒)は各プレイヤーの開始時の条件。
+/// unusedはコマンドで使わない引数のキーで、指定されていたらNoneを返す。
+fn parse_battle_args<'a>(args: &[&'a str], unused: &[&str]) -> Option<BattleArgs<'a>> {
+    let mut dirs = vec![];
+    let mut kinds = [StrategyKind::Default; 2];
+    let mut seed = None;
+    let mut num = None;
+    let mut rules = BattleRules::default();
+    let mut options = [PlayerOptions::default(); 2];
+    let mut events = false;
+
+    for arg in args {
+        if let Some((key, val)) = arg.split_once('=') {
+            if unused.contains(&key) {
+                return None;
+            }
+
+            let i = match key {
+                "s0" => 0,
+                "s1" => 1,
+                "seed" => {
+                    seed = Some(val.parse().ok()?);
+                    continue;
+                },
+                "n" => {
+                    num = Some(val.parse().ok().filter(|n| *n > 0)?);
+                    continue;
+                },
+                "rules" => {
+                    // cmやtimingの指定は残す
+                    rules = BattleRules { cm_bonus: rules.cm_bonus, fast_move_timing: rules.fast_move_timing, ..rules_by_name(val)? };
+                    continue;
+                },
+                "cm" => {
+                    rules.cm_bonus = val.parse().ok().filter(|x| (0.0..=1.0).contains(x))?;
+                    continue;
+                },
+                "timing" => {
+                    rules.fast_move_timing = val.parse().ok()?;
+                    continue;
+                },
+                "events" => {
+                    events = match val {
+                        "y" => true,
+                        "n" => false,
+                        _ => return None,
+                    };
+                    continue;
+                },
+                _ if key.ends_with(['0', '1']) => {
+                    let i = if key.ends_with('0') { 0 } else { 1 };
+                    let o = &mut options[i];
+
+                    match &key[..key.len() - 1] {
+                        "shields" => o.num_shields = Some(val.parse().ok().filter(|n| *n >= 0)?),
+                        "energy" => o.energy = val.parse().ok().filter(|n| (0..=100).contains(n))?,
+                        "hp" => o.hp_percent = val.parse().ok().filter(|n| (1..=100).contains(n))?,
+                        "buff" => {
+                            let (a, d) = val.split_once(',')?;
+                            o.buff = (a.parse().ok()?, d.parse().ok()?);
+
+                            if !(-4..=4).contains(&o.buff.0) || !(-4..=4).contains(&o.buff.1) {
+                                return None;
+                            }
+                        },
+                        "switch" => o.switch_ms = val.parse::<i32>().ok().filter(|n| *n >= 0)? * 1000,
+                        _ => return None,
+                    }
+
+                    continue;
+                },
+                _ => return None,
+            };
+
+            match strategy_by_name(val) {
+                None => {
+                    eprintln!("存在しない戦略: {}", val);
+                    return None;
+                },
+                Some(kind) => kinds[i] = kind,
+            }
+        } else {
+            dirs.push(*arg);
+        }
+    }
+
+    if dirs.len() > 2 {
+        return None;
+    }
+
+    Some(BattleArgs { dirs, kinds, seed, num, rules, options, events })
+}
+
+/// host, joinコマンドの引数
+struct NetArgs<'a> {
+    dir: Option<&'a str>,  // チームのディレクトリ
+    kind: Option<StrategyKind>,  // AIに操作させる場合の戦略。Noneなら自分で操作する
+    seed: Option<u64>,  // 乱数の種
+    rules: BattleRules,  // バトルのルール
+    bind: &'a str,  // 接続を待つアドレス
+}
+
+impl NetArgs<'_> {
+    fn strategy(&self) -> Box<dyn BattleStrategy> {
+        match self.kind {
+            Some(kind) => kind.create(),
+            None => Box::new(HumanStrategy::stdio()),
+        }
+    }
+}
+
+/// host, joinコマンドの引数を解析する。"ai=greedy"は戦略、"seed=123"は乱数の種、"rules=noshield"はルール、
+/// "bind=0.0.0.0"は接続を待つアドレス(省略すると127.0.0.1)、それ以外はディレクトリとする。unusedはコマンドで使わない引数のキーで、指定されていたらNoneを返す。
+fn parse_net_args<'a>(args: &[&'a str], unused: &[&str]) -> Option<NetArgs<'a>> {
+    let mut dir = None;
+    let mut kind = None;
+    let mut seed = None;
+    let mut rules = BattleRules::default();
+    let mut bind = "127.0.0.1";
+
+    for arg in args {
+        match arg.split_once('=') {
+            Some((key, _)) if unused.contains(&key) => return None,
+            Some(("ai", val)) => match strategy_by_name(val) {
+                None => {
+                    eprintln!("存在しない戦略: {}", val);
+                    return None;
+                },
+                Some(k) => kind = Some(k),
+            },
+            Some(("seed", val)) => seed = Some(val.parse().ok()?),
+            Some(("rules", val)) => rules = rules_by_name(val)?,
+            Some(("bind", val)) => bind = val,
+            Some(_) => return None,
+            None if dir.is_none() => dir = Some(*arg),
+            None => return None,
+        }
+    }
+
+    Some(NetArgs { dir, kind, seed, rules, bind })
+}
+
+/// 名前からルールを作る。存在しない名前なら使える名前を表示してNoneを返す
+fn rules_by_name(name: &str) -> Option<BattleRules> {
+    let rules = BattleRules::by_name(name);
+
+    if rules.is_none() {
+        let names = RULES_NAMES.iter().map(|r| format!("{}({})", r[0], r[1])).collect::<Vec<_>>();
+        eprintln!("存在しないルール: {} (使えるルール: {})", name, names.join(", "));
+    }
+
+    rules
+}
+
+/// バトルのランキングを返す。まだ計算していないか、カップか戦略が違う場合は計算する。
+fn get_battle_ranking(cache: &mut Option<BattleRanking>, cup: Cup, kind: StrategyKind) -> &BattleRanking {
+    if cache.as_ref().map(|r| r.cup != cup || r.kind != kind).unwrap_or(true) {
+        println!("{}のランキングを計算中...", cup);
+        *cache = Some(BattleRanking::for_cup(cup, kind));
+    }
+
+    cache.as_ref().unwrap()
+}
+
+/// 引数から"league=ultra:50"のようなリーグの指定を取り除き、cupのリーグをそれに変えたカップと残りの引数を返す。
+/// 指定がなければcupのまま。cupがNoneならスーパーリーグ。リーグの名前が正しくなければNone
+fn take_cup<'a>(args: &[&'a str], cup: Option<&Cup>) -> Option<(Cup, Vec<&'a str>)> {
+    let cup = cup.cloned().unwrap_or_else(|| Cup::open(League::default()));
+    let mut league = cup.league;
+    let mut rest = vec![];
+
+    for arg in args {
+        match arg.strip_prefix("league=") {
+            Some(name) => match name.parse() {
+                Ok(l) => league = l,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return None;
+                },
+            },
+            None => rest.push(*arg),
+        }
+    }
+
+    Some((Cup { league, ..cup }, rest))
+}
+
+/// pokemonsのうちcupで使えるポケモン。使えないポケモンは名前と理由を表示する。cupがNoneならすべて
+fn eligible_pokemons(pokemons: &[Pokemon], cup: Option<&Cup>) -> Vec<Pokemon> {
+    let cup = match cup {
+        None => return pokemons.to_vec(),
+        Some(cup) => cup,
+    };
+
+    let mut v = vec![];
+
+    for p in pokemons {
+        match cup.ineligible_reason(p) {
+            None => v.push(p.clone()),
+            Some(reason) => eprintln!("{}は{}で使えない({})", p.name(), cup.name, reason),
+        }
+    }
+
+    v
+}
+
+/// カップの制限を表示する
+fn print_cup(cup: &Cup) {
+    println!("{}", cup);
+
+    let names = |nos: &[&str]| nos.iter().filter_map(|no| pokepedia::pokepedia_by_no(no)).map(|d| d.name()).collect::<Vec<_>>().join(", ");
+
+    if !cup.types.is_empty() {
+        let types = cup.types.iter().map(|t| String::from(*t)).collect::<Vec<_>>();
+        println!("    タイプ: {}", types.join(", "));
+    }
+
+    if !cup.allowed.is_empty() {
+        println!("    使えるポケモン: {}", names(&cup.allowed));
+    }
+
+    if !cup.banned.is_empty() {
+        println!("    使えないポケモン: {}", names(&cup.banned));
+    }
+}
+
+fn print_battle_ranking(ranking: &BattleRanking, num: usize) {
+    let v = ranking.ranking();
+    let width = v.iter().take(num).map(|i| jp_width(ranking.pokemons[*i].name())).max().unwrap_or(0);
+    let scenarios = SHIELD_SCENARIOS.iter().map(|s| format!("  {}-{}", s[0], s[1])).collect::<Vec<_>>();
+
+    println!("     {} score {}", jp_fixed_width_string("", width), scenarios.join(" "));
+
+    for (rank, i) in v.into_iter().take(num).enumerate() {
+        let p = &ranking.pokemons[i];
+        let scores = (0..SHIELD_SCENARIOS.len()).map(|s| format!("{:>5.0}", ranking.scenario_score(s, i))).collect::<Vec<_>>();
+
+        println!("{:>4} {} {:>5.0} {}  {} | {} | {}", rank + 1, jp_fixed_width_string(p.name(), width),
+                 ranking.scores[i], scores.join(" "),
+                 p.fast_move().name(), p.charge_move1().name(), p.charge_move2().map(|mv| mv.name()).unwrap_or("None"));
+    }
+}
+
+/// pokemonsの役割ごとのスコアを、opponentsの全ポケモンとの対戦から求めて表示する
+fn print_role_scores(opponents: &[Pokemon], kind: StrategyKind, pokemons: &[Pokemon]) {
+    let width = pokemons.iter().map(|p| jp_width(p.name())).max().unwrap_or(0);
+    let scenarios = ROLE_SCENARIOS.iter().map(|s| format!("{:>8}", s.name)).collect::<Vec<_>>();
+
+    println!("{} score {}", jp_fixed_width_string("", width), scenarios.join(" "));
+
+    let scores = par_map(pokemons, |p| ranking::role_scores(p, opponents, kind));
+
+    for (p, scores) in pokemons.iter().zip(scores) {
+        let avg = scores.iter().sum::<f64>() / scores.len() as f64;
+        let cols = scores.iter().map(|s| format!("{:>8.0}", s)).collect::<Vec<_>>();
+
+        println!("{} {:>5.0} {}  {} | {} | {}", jp_fixed_width_string(p.name(), width), avg, cols.join(" "),
+                 p.fast_move().name(), p.charge_move1().name(), p.charge_move2().map(|mv| mv.name()).unwrap_or("None"));
+    }
+}
+
+fn print_battle_ranking_detail(ranking: &BattleRanking, i: usize) {
+    let p = &ranking.pokemons[i];
+    let rank = ranking.ranking().iter().position(|k| *k == i).unwrap() + 1;
+
+    println!("{}位 {}", rank, p.format(jp_width(p.name())));
+
+    for (scenario_i, shields) in SHIELD_SCENARIOS.iter().enumerate() {
+        let mut v = (0..ranking.pokemons.len()).filter(|j| *j != i).collect::<Vec<_>>();
+        v.sort_by_key(|j| -ranking.rating(scenario_i, i, *j));
+
+        let format = |v: &[usize]| v.iter()
+            .map(|j| format!("{}({})", ranking.pokemons[*j].name(), ranking.rating(scenario_i, i, *j)))
+            .collect::<Vec<_>>().join(", ");
+
+        println!("シールド{}-{}: スコア {:.0}", shields[0], shields[1], ranking.scenario_score(scenario_i, i));
+        println!("    勝ち: {}", format(&v[..std::cmp::min(5, v.len())]));
+        v.reverse();
+        println!("    負け: {}", format(&v[..std::cmp::min(5, v.len())]));
+    }
+}
+
+fn print_monte_carlo(result: &MonteCarloResult, names: [&str; 2]) {
+    let (win, draw, loss) = result.win_draw_loss();
+    println!("{}回, 勝ち {:.1}%, 引き分け {:.1}%, 負け {:.1}%", result.num_battles(), win * 100.0, draw * 100.0, loss * 100.0);
+
+    let (min, avg, max) = result.turn_stats();
+    println!("ターン数: 最小 {}, 平均 {:.1}, 最大 {}", min, avg, max);
+
+    let width = names.iter().map(|name| jp_width(name)).max().unwrap();
+
+    println!("残りHP(%) {}   0- 10- 20- 30- 40- 50- 60- 70- 80- 90-", jp_fixed_width_string("", width));
+
+    for (player_i, name) in names.iter().enumerate() {
+        let hist = result.hp_histogram(player_i, 10);
+        let cols = hist.iter().map(|n| format!("{:>3.0}", *n as f64 * 100.0 / result.num_battles() as f64)).collect::<Vec<_>>();
+        println!("          {} {}", jp_fixed_width_string(name, width), cols.join(" "));
+    }
+
+    if !result.buff_counts.is_empty() {
+        println!("ステータス変化:");
+    }
+
+    for c in &result.buff_counts {
+        println!("    {} {} {}/{} ({:.1}%, 確率 {}%)", names[c.player_i], c.mv.name(), c.num_procs, c.num_uses,
+                 c.ratio() * 100.0, c.mv.buff_prob());
+    }
+}
+
+fn print_battle(battle: &Battle) {
+    let start = &battle.states[0];
+    let width = start.player0.team.iter().chain(start.player1.team.iter())
+        .map(|p| jp_width(p.name())).max().unwrap();
+
+    println!("{}({}) vs {}({}), seed = {}", start.player0.name, battle.strategies[0].name(),
+             start.player1.name, battle.strategies[1].name(), battle.seed);
+
+    for log in &battle.logs {
+        println!("{}", log.format(width));
+    }
+
+    println!("{}", result_string(battle.state()));
+}
+
+/// 人が操作するバトルを進める。ターンごとにログを表示する
+fn play_battle(battle: &mut Battle) {
+    let start = &battle.states[0];
+    let width = start.player0.team.iter().chain(start.player1.team.iter())
+        .map(|p| jp_width(p.name())).max().unwrap();
+
+    println!("{} vs {}({}), seed = {}", start.player0.name, start.player1.name, battle.strategies[1].name(), battle.seed);
+
+    loop {
+        let actions = battle.next_actions();
+        let cont = battle.do_action(actions);
+
+        if let Some(log) = battle.logs.last() {
+            println!("{}", log.format(width));
+        }
+
+        if !cont {
+            break;
+        }
+    }
+
+    println!("{}", result_string(battle.state()));
+
+    for line in timeline::format_timeline(battle, 60) {
+        println!("{}", line);
+    }
+}
+
+/// バトルの各ターンを前後に移動しながら表示する
+fn view_battle(rl: &mut DefaultEditor, battle: &Battle) {
+    let start = &battle.states[0];
+    let width = start.player0.team.iter().chain(start.player1.team.iter())
+        .map(|p| jp_width(p.name())).max().unwrap();
+    let last = battle.logs.len();
+
+    println!("{}({}) vs {}({}), seed = {}, {}ターン", start.player0.name, battle.strategies[0].name(),
+             start.player1.name, battle.strategies[1].name(), battle.seed, last);
+    println!("Enter, n: 次のターン, p: 前のターン, 数字: そのターンへ移動, q: 終了");
+
+    let mut turn = 0;
+
+    loop {
+        let state = &battle.states[turn];
+
+        if turn == 0 {
+            println!("開始時");
+        } else {
+            println!("{} {:?}", battle.logs[turn - 1].format(width), battle.actions[turn - 1]);
+        }
+
+        for p in [&state.player0, &state.player1] {
+            let team = p.team.iter().enumerate().map(|(i, poke)| {
+                let mark = if i == p.cur_poke { "*" } else { " " };
+                format!("{}{} HP {:>3}/{:>3} E {:>3}", mark, jp_fixed_width_string(poke.name(), width), poke.hp(), poke.base_hp(), poke.energy)
+            }).collect::<Vec<_>>();
+
+            println!("    {} S {} | {}", p.name, p.num_shields, team.join(" | "));
+        }
+
+        if turn == last {
+            println!("{}", result_string(state));
+        }
+
+        let line = match rl.readline(&format!("replay {}/{} > ", turn, last)) {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        match line.trim() {
+            "" | "n" => turn = std::cmp::min(turn + 1, last),
+            "p" => turn = turn.saturating_sub(1),
+            "q" => break,
+            s => match s.parse::<usize>() {
+                Ok(t) if t <= last => turn = t,
+                _ => eprintln!("0から{}のターンを指定してください。", last),
+            },
+        }
+    }
+}
+
+fn result_string(state: &State) -> String {
+    match state.phase {
+        Phase::GameOver(2) | Phase::TimeOver(2) => String::from("引き分け"),
+        Phase::GameOver(i) => format!("{}の勝ち", state.player(i.into()).name),
+        Phase::TimeOver(i) => format!("時間切れ。{}の勝ち", state.player(i.into()).name),
+        Phase::Neutral => String::from("バトル中"),
+    }
+}
+
+struct BranchArgs {
+    turn: usize,
+    actions: [Option<Action>; 2],
+    shields: [Option<bool>; 2],
+}
+
+fn parse_branch_args(args: &[&str]) -> Option<BranchArgs> {
+    let turn = args.first()?.parse().ok()?;
+    let mut actions = [None; 2];
+    let mut shields = [None; 2];
+
+    for arg in &args[1..] {
+        let (key, val) = arg.split_once('=')?;
+
+        match key {
+            "a0" | "a1" => {
+                actions[if key == "a0" { 0 } else { 1 }] = Some(val.parse().ok()?);
+            },
+
+            "shield0" | "shield1" => {
+                let shield = match val {
+                    "y" => true,
+                    "n" => false,
+                    _ => return None,
+                };
+
+                shields[if key == "shield0" { 0 } else { 1 }] = Some(shield);
+            },
+
+            _ => return None,
+        }
+    }
+
+    Some(BranchArgs { turn, actions, shields })
+}
+
+/// 分岐したバトルのturn以降のログと、元のバトルとの比較を表示する
+fn print_branch(original: &Battle, branch: &Battle, turn: usize) {
+    let start = &branch.states[0];
+    let width = start.player0.team.iter().chain(start.player1.team.iter())
+        .map(|p| jp_width(p.name())).max().unwrap();
+
+    for log in &branch.logs[turn..] {
+        println!("{}", log.format(width));
+    }
+
+    println!();
+
+    let labels = [
+        String::from("結果"), String::from("ターン数"), format!("{}のレーティング", start.player0.name),
+        format!("{}の残りHP", start.player0.name), format!("{}の残りHP", start.player1.name),
+        format!("{}のシールド", start.player0.name), format!("{}のシールド", start.player1.name),
+    ];
+
+    let summary = |b: &Battle| {
+        let state = b.state();
+        [
+            result_string(state), state.turn.to_string(), b.rating(0).to_string(),
+            state.player0.sum_hp().to_string(), state.player1.sum_hp().to_string(),
+            state.player0.num_shields.to_string(), state.player1.num_shields.to_string(),
+        ]
+    };
+
+    let columns = [summary(original), summary(branch)];
+
+    let label_width = labels.iter().map(|label| jp_width(label)).max().unwrap();
+    let value_width = columns.iter().flatten().map(|v| jp_width(v)).max().unwrap().max(10);
+
+    println!("{} {} {}", jp_fixed_width_string("", label_width),
+             jp_fixed_width_string("元のバトル", value_width), jp_fixed_width_string("分岐", value_width));
+
+    for (i, label) in labels.iter().enumerate() {
+        println!("{} {} {}", jp_fixed_width_string(label, label_width),
+                 jp_fixed_width_string(&columns[0][i], value_width), jp_fixed_width_string(&columns[1][i], value_width));
+    }
+}
+
+fn print_solve_result(start: &State, result: &SolveResult, max_turns: i32) {
+    let width = start.player0.team.iter().chain(start.player1.team.iter())
+        .map(|p| jp_width(p.name())).max().unwrap();
+
+    println!("{} vs {}, {}ターン先まで探索", start.player0.name, start.player1.name, max_turns);
+
+    for step in &result.line {
+        println!("{} {:?}", step.log.format(width), step.actions);
+    }
+
+    match result.line.last().map(|step| &step.state) {
+        Some(State { phase: Phase::GameOver(2) | Phase::TimeOver(2), .. }) => println!("最善を尽くすと引き分け"),
+        Some(state @ State { phase: Phase::GameOver(i) | Phase::TimeOver(i), .. }) => {
+            println!("最善を尽くすと{}の勝ち", state.player((*i).into()).name);
+        },
+        _ => println!("探索したターン内では決着しない"),
+    }
+
+    println!("評価値(バトルレーティング) = {:.1}, 状態数 = {}", result.value, result.num_nodes);
+}
+
 fn print_ecp_table(poke: &Pokemon) {
     println!();
 
@@ -530,9 +1737,9 @@ fn print_ecp_table(poke: &Pokemon) {
         print!("{}| ", name);
 
         let types = vec![TYPES[i]];
-        let ecp2 = poke.ecp(None, Some(types.clone()), 2);
-        let ecp1 = poke.ecp(None, Some(types.clone()), 1);
-        let ecp0 = poke.ecp(None, Some(types), 0);
+        let ecp2 = poke.format_ecp(None, Some(types.clone()), 2);
+        let ecp1 = poke.format_ecp(None, Some(types.clone()), 1);
+        let ecp0 = poke.format_ecp(None, Some(types), 0);
 
         println!(" {:>4}   {:>4}   {:>4}", ecp2, ecp1, ecp0);
     }
@@ -548,7 +1755,14 @@ fn create_pokemon() -> Option<Pokemon> {
     println!("ポケモン: {}", dict.name());
 
     let mut cp = read_cp();
-    let mut ivs = read_ivs();
+
+    let (mut ivs, candidates) = match read_ivs_or_appraisal() {
+        IvsInput::Exact(ivs) => (ivs, vec![]),
+        IvsInput::Appraisal(appraisal) => match read_ivs_candidates(dict, &mut cp, appraisal) {
+            None => { return None; },
+            Some(candidates) => (candidates[0], candidates),
+        },
+    };
 
     let lv = match calc_lv_or_read_again(dict, &mut cp, &mut ivs) {
         None => { return None; },
@@ -568,7 +1782,10 @@ fn create_pokemon() -> Option<Pokemon> {
         println!("スペシャルアタック2: {}", mv.name());
     }
 
-    Some(Pokemon::raw_new(dict, lv, ivs, fast_move, charge_move1, charge_move2))
+    let mut poke = Pokemon::raw_new(dict, lv, ivs, fast_move, charge_move1, charge_move2);
+    poke.set_ivs_candidates(candidates);
+
+    Some(poke)
 }
 
 fn select_pokemon(pokemons: &[Pokemon]) -> Option<&Pokemon> {
@@ -605,7 +1822,7 @@ fn edit_pokemon(poke: &mut Pokemon) {
     loop {
         let mut input = String::new();
 
-        print!("(q)uit, (c)p, (f)ast move, charge move(1) | (2), (r)emove charge move 2: ");
+        print!("(q)uit, (c)p, (i)vs, (f)ast move, charge move(1) | (2), (r)emove charge move 2: ");
         io::stdout().flush().unwrap();
         io::stdin().read_line(&mut input).expect("読み込みに失敗");
 
@@ -618,6 +1835,7 @@ fn edit_pokemon(poke: &mut Pokemon) {
                     eprintln!("CPが間違っている");
                 }
             },
+            "i" => edit_ivs(poke),
             "f" => {
                 let fast_move = read_fast_move(poke.dict());
                 poke.set_fast_move(fast_move);
@@ -643,6 +1861,38 @@ fn edit_pokemon(poke: &mut Pokemon) {
     }
 }
 
+/// 個体値を入力しなおす。正確な個体値ならそれに置き換える。
+/// 鑑定結果なら今の候補をさらに絞り込み、合うものがなければ鑑定結果の候補に置き換えるかを聞く
+fn edit_ivs(poke: &mut Pokemon) {
+    let candidates = match read_ivs_or_appraisal() {
+        IvsInput::Exact(ivs) => vec![ivs],
+        IvsInput::Appraisal(appraisal) => {
+            let candidates = appraisal::candidate_ivs(poke.dict(), poke.cp(), read_hp(), &appraisal);
+            let narrowed = candidates.iter().copied().filter(|ivs| poke.ivs_candidates().contains(ivs)).collect::<Vec<_>>();
+
+            if !narrowed.is_empty() {
+                narrowed
+            } else if !candidates.is_empty() && yes_or_no("今の個体値の候補と合わない。鑑定結果の候補に置き換える？(Yesならyを入力): ") {
+                candidates
+            } else {
+                eprintln!("個体値を変更しない");
+                return;
+            }
+        },
+    };
+
+    if !poke.replace_ivs(candidates) {
+        eprintln!("CP{}になる個体値がない", poke.cp());
+        return;
+    }
+
+    if poke.is_ivs_fixed() {
+        println!("個体値を{:?}に変更", poke.ivs().to_tuple());
+    } else {
+        println!("個体値の候補: {}通り {}", poke.ivs_candidates().len(), appraisal::format_ivs_range(poke.ivs_candidates()));
+    }
+}
+
 fn remove_pokemons(pokemons: &mut Vec<Pokemon>) -> bool {
     let width = pokemons.iter().map(|p| jp_width(p.name())).max();
 
@@ -714,6 +1964,97 @@ fn read_ivs() -> IVs {
     }
 }
 
+/// 個体値の入力。正確な個体値か鑑定結果
+enum IvsInput {
+    Exact(IVs),
+    Appraisal(Appraisal),
+}
+
+fn read_ivs_or_appraisal() -> IvsInput {
+    loop {
+        let mut input = String::new();
+
+        println!("個体値を空白区切りで入力。わからなければ鑑定結果を入力。");
+        println!("  星の数(0～4。4は100%) [攻撃 防御 HPのバーの目盛り(0～3)] か 割合(80-90%)");
+        print!("IVs(atk def sta) or appraisal: ");
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut input).expect("個体値の読み込みに失敗");
+
+        let nums = input.split_whitespace().map(|s| s.parse::<i32>()).collect::<Vec<_>>();
+
+        if nums.len() == 3 && nums.iter().all(|n| n.is_ok()) {
+            let nums = nums.into_iter().map(|n| n.unwrap()).collect::<Vec<_>>();
+
+            match IVs::new(nums[0], nums[1], nums[2]) {
+                Ok(ivs) => return IvsInput::Exact(ivs),
+                Err(_) => {
+                    eprintln!("値の範囲が正しくありません。0から15の値で入力してください。");
+                    continue;
+                },
+            }
+        }
+
+        match input.parse::<Appraisal>() {
+            Ok(appraisal) => return IvsInput::Appraisal(appraisal),
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+}
+
+fn read_hp() -> Option<i32> {
+    loop {
+        let mut hp_str = String::new();
+
+        print!("HP(わからなければ空): ");
+        io::stdout().flush().unwrap();
+        io::stdin().read_line(&mut hp_str).expect("HPの読み込みに失敗");
+
+        let hp_str = hp_str.trim();
+
+        if hp_str.is_empty() {
+            return None;
+        }
+
+        match hp_str.parse() {
+            Ok(hp) if hp > 0 => return Some(hp),
+            _ => eprintln!("HPは正の数で入力してください。"),
+        }
+    }
+}
+
+/// 鑑定結果とCP、HPから個体値の候補を絞り込む。候補がなければ入力しなおす
+fn read_ivs_candidates(dict: &'static Pokepedia, cp: &mut i32, mut appraisal: Appraisal) -> Option<Vec<IVs>> {
+    loop {
+        let hp = read_hp();
+        let candidates = appraisal::candidate_ivs(dict, *cp, hp, &appraisal);
+
+        if candidates.is_empty() {
+            eprintln!("{}: CP{}、鑑定結果({})に合う個体値がない。", dict.name(), *cp, appraisal);
+
+            if !yes_or_no("CPと鑑定結果を入力しなおす？(Yesならyを入力): ") {
+                return None;
+            }
+
+            *cp = read_cp();
+
+            appraisal = match read_ivs_or_appraisal() {
+                IvsInput::Exact(ivs) => return Some(vec![ivs]),
+                IvsInput::Appraisal(appraisal) => appraisal,
+            };
+
+            continue;
+        }
+
+        if candidates.len() == 1 {
+            println!("個体値: {:?}", candidates[0].to_tuple());
+        } else {
+            println!("個体値の候補: {}通り {}", candidates.len(), appraisal::format_ivs_range(&candidates));
+        }
+
+        return Some(candidates);
+    }
+}
+
 fn read_fast_move(dict: &'static Pokepedia) -> &'static FastMove {
     loop {
         if let Some(mv) = moves::skim_fast_move_in_dict(dict) {
@@ -0,0 +1,207 @@
+//! モンテカルロ法によるバトルの勝率計算
+//! ステータス変化の確率やスペシャルアタックの先行判定で結果が変わるので、
+//! 乱数の種を変えながら同じ対戦を何度も行って統計をとる。
+
+use std::collections::HashMap;
+use std::thread;
+
+use crate::battle::{Battle, Event, BattleRules, PlayerOptions};
+use crate::moves::ChargeMove;
+use crate::pokemon::Pokemon;
+use crate::strategy::StrategyKind;
+
+/// ステータス変化の発生回数
+#[derive(Debug, Clone)]
+pub struct BuffCount {
+    pub player_i: usize,
+    pub mv: &'static ChargeMove,
+    pub num_uses: usize,  // スペシャルアタックを使った回数
+    pub num_procs: usize,  // ステータス変化が起きた回数
+}
+
+impl BuffCount {
+    /// ステータス変化が起きた割合
+    pub fn ratio(&self) -> f64 {
+        self.num_procs as f64 / self.num_uses as f64
+    }
+}
+
+/// モンテカルロ法の結果
+#[derive(Debug, Clone, Default)]
+pub struct MonteCarloResult {
+    pub num_wins: [usize; 3],  // 勝利数([0] プレイヤー0の勝ち, [1] プレイヤー1の勝ち, [2] 引き分け)
+    pub hp_ratios: [Vec<f64>; 2],  // バトル終了時に残ったHPの割合(0.0～1.0)。チーム全体の合計
+    pub turns: Vec<i32>,  // バトル終了までのターン数
+    pub buff_counts: Vec<BuffCount>,  // ステータス変化が確率で起きるスペシャルアタックの発生回数
+}
+
+impl MonteCarloResult {
+    pub fn num_battles(&self) -> usize {
+        self.turns.len()
+    }
+
+    /// プレイヤー0から見た(勝ち, 引き分け, 負け)の確率
+    pub fn win_draw_loss(&self) -> (f64, f64, f64) {
+        let n = self.num_battles() as f64;
+
+        (self.num_wins[0] as f64 / n, self.num_wins[2] as f64 / n, self.num_wins[1] as f64 / n)
+    }
+
+    /// 残りHPの割合をnum_bins個の区間に分けて数える。
+    /// HPが0のときは最初の区間に、満タンのときは最後の区間に入る。
+    pub fn hp_histogram(&self, player_i: usize, num_bins: usize) -> Vec<usize> {
+        let mut v = vec![0; num_bins];
+
+        for r in &self.hp_ratios[player_i] {
+            let i = std::cmp::min((r * num_bins as f64) as usize, num_bins - 1);
+            v[i] += 1;
+        }
+
+        v
+    }
+
+    /// ターン数の(最小, 平均, 最大)
+    pub fn turn_stats(&self) -> (i32, f64, i32) {
+        let min = self.turns.iter().copied().min().unwrap_or(0);
+        let max = self.turns.iter().copied().max().unwrap_or(0);
+        let avg = self.turns.iter().sum::<i32>() as f64 / self.turns.len() as f64;
+
+        (min, avg, max)
+    }
+
+    fn add_battle(&mut self, battle: &Battle, buff_map: &mut HashMap<(usize, &'static str), BuffCount>) {
+        if let Some(winner) = battle.winner() {
+            self.num_wins[winner as usize] += 1;
+        }
+
+        let state = battle.state();
+
+        for (player_i, p) in [&state.player0, &state.player1].into_iter().enumerate() {
+            let max_hp = p.team.iter().map(|poke| poke.base_hp()).sum::<i32>();
+            self.hp_ratios[player_i].push(p.sum_hp() as f64 / max_hp as f64);
+        }
+
+        self.turns.push(state.turn);
+
+        for log in &battle.logs {
+            for (player_i, events) in [&log.events0, &log.events1].into_iter().enumerate() {
+                for e in events {
+                    if let Event::ChargeMove(mv, _, is_buffed) = e {
+                        if mv.buff().is_none() || mv.buff_prob() >= 100.0 {
+                            continue;
+                        }
+
+                        let count = buff_map.entry((player_i, mv.no())).or_insert(BuffCount {
+                            player_i, mv, num_uses: 0, num_procs: 0,
+                        });
+
+                        count.num_uses += 1;
+
+                        if *is_buffed {
+                            count.num_procs += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        for i in 0..3 {
+            self.num_wins[i] += other.num_wins[i];
+        }
+
+        for i in 0..2 {
+            self.hp_ratios[i].extend(other.hp_ratios[i].iter());
+        }
+
+        self.turns.extend(other.turns.iter());
+
+        for count in other.buff_counts {
+            match self.buff_counts.iter_mut().find(|c| c.player_i == count.player_i && c.mv.no() == count.mv.no()) {
+                Some(c) => {
+                    c.num_uses += count.num_uses;
+                    c.num_procs += count.num_procs;
+                },
+                None => self.buff_counts.push(count),
+            }
+        }
+    }
+}
+
+/// team0とteam1の対戦をnum_battles回行う。
+/// i回目のバトルの乱数の種はseed + iになるので、同じ引数なら同じ結果になる。
+/// バトルは使えるCPUの数だけスレッドに分けて実行する。
+pub fn run(team0: &[Pokemon], team1: &[Pokemon], kinds: [StrategyKind; 2], rules: BattleRules, options: [PlayerOptions; 2], num_battles: usize, seed: u64) -> MonteCarloResult {
+    let num_threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = num_battles.div_ceil(num_threads).max(1);
+
+    let results = thread::scope(|s| {
+        let handles = (0..num_battles).step_by(chunk_size).map(|from| {
+            let to = std::cmp::min(from + chunk_size, num_battles);
+
+            s.spawn(move || run_range(team0, team1, kinds, rules, options, from..to, seed))
+        }).collect::<Vec<_>>();
+
+        handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>()
+    });
+
+    let mut result = MonteCarloResult::default();
+
+    for r in results {
+        result.merge(r);
+    }
+
+    result.buff_counts.sort_by_key(|c| (c.player_i, c.mv.no()));
+
+    result
+}
+
+fn run_range(team0: &[Pokemon], team1: &[Pokemon], kinds: [StrategyKind; 2], rules: BattleRules, options: [PlayerOptions; 2], range: std::ops::Range<usize>, seed: u64) -> MonteCarloResult {
+    let mut result = MonteCarloResult::default();
+    let mut buff_map = HashMap::new();
+
+    for i in range {
+        let mut battle = Battle::new(String::from("player0"), team0.to_vec(), String::from("player1"), team1.to_vec());
+        battle.set_strategy(0, kinds[0].create());
+        battle.set_strategy(1, kinds[1].create());
+        battle.set_rules(rules);
+        battle.set_player_options(0, &options[0]);
+        battle.set_player_options(1, &options[1]);
+        battle.set_seed(seed.wrapping_add(i as u64));
+        battle.start();
+
+        result.add_battle(&battle, &mut buff_map);
+    }
+
+    result.buff_counts = buff_map.into_values().collect();
+
+    result
+}
+
+#[test]
+fn test_run() {
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let team0 = vec![p0, p2.clone()];
+    let team1 = vec![p1, p2];
+    let kinds = [StrategyKind::Random, StrategyKind::Greedy];
+
+    let r0 = run(&team0, &team1, kinds, BattleRules::default(), [PlayerOptions::default(); 2], 40, 100);
+    let r1 = run(&team0, &team1, kinds, BattleRules::default(), [PlayerOptions::default(); 2], 40, 100);
+
+    assert_eq!(r0.num_battles(), 40);
+    assert_eq!(r0.num_wins.iter().sum::<usize>(), 40);
+    assert_eq!(r0.num_wins, r1.num_wins);
+    assert_eq!(r0.turns, r1.turns);
+    assert_eq!(r0.hp_histogram(0, 10).iter().sum::<usize>(), 40);
+
+    let (win, draw, loss) = r0.win_draw_loss();
+    assert!((win + draw + loss - 1.0).abs() < 1e-9);
+
+    for c in &r0.buff_counts {
+        assert!(c.num_procs <= c.num_uses);
+    }
+}
@@ -12,7 +12,7 @@ use crate::utils::NameItem;
 pub const STAB: f64 = 1.2;  // STAB(Same Type Attack Bonus, タイプ一致ボーナス)
 
 // ノーマル技構造体
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct FastMove {
     no: &'static str,
     name: &'static str,
@@ -190,6 +190,7 @@ pub fn skim_charge_move_in_dict(dict: &'static Pokepedia) -> Option<&'static Cha
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn test_real_power() {
     let m = fast_move_by_name("マッドショット").unwrap();
     assert_eq!(m.mtype(), Type::Ground);
@@ -201,11 +202,11 @@ fn test_real_power() {
 /// ステータス変化構造体。
 /// 値の意味は(自分の攻撃, 自分の防御, 相手の攻撃, 相手の防御)
 /// 例えば相手の攻撃が-1になるならBuff(0, 0, -1, 0)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Buff(pub i8, pub i8, pub i8, pub i8);
 
 /// スペシャル技構造体
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct ChargeMove {
     no: &'static str,
     name: &'static str,
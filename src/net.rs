@@ -0,0 +1,440 @@
+//! TCPで2人がバトルする
+//! ホストがバトルの状態を持ち、Battle::do_actionでバトルを進める。クライアントは聞かれたときに行動などを返すだけ。
+//! ホストがプレイヤー0、クライアントがプレイヤー1になる。
+//!
+//! 1行に1つのメッセージを送る。最初の単語がコマンドで、残りが引数。
+//! 複数行のデータ(TOML)は"コマンド 行数"の後にその行数だけ続ける。行数がMAX_BLOCK_LINESを超えるデータは受け取らない。
+//! 相手からTIMEOUTの間メッセージが届かなければ接続が切れたとみなす。
+//!
+//! クライアント → ホスト
+//! - hello 名前
+//! - team 行数 (ポケモンのTOML)
+//! - action fast | cm1 | cm2 | sw<番号> | none
+//! - shield y | n
+//! - switch 番号
+//!
+//! ホスト → クライアント
+//! - start プレイヤー番号
+//! - state 行数 (シナリオのTOML)
+//! - action: 行動を聞く
+//! - shield 技の名前: 相手のスペシャルアタックにシールドを使うかを聞く
+//! - switch: 瀕死になったときに次に出すポケモンを聞く
+//! - log ターンのログ
+//! - end gameover | timeover 勝者の番号
+
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Write, Cursor};
+use std::net::TcpStream;
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::{Result, bail, anyhow};
+use rand::SeedableRng;
+
+use crate::battle::{Battle, State, Action, BattleRng, Phase, BattleRules};
+use crate::cup::Cup;
+use crate::moves::{ChargeMove, charge_move_by_name};
+use crate::pokemon::{Pokemon, load_pokemons, save_pokemons};
+use crate::scenario::ScenarioToml;
+use crate::strategy::BattleStrategy;
+use crate::utils::jp_width;
+
+/// 送受信を待つ最長の時間。人が操作するときに考える時間も含む
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// 複数行のデータの最大の行数。チームやバトルの状態のTOMLはこれより十分短い
+const MAX_BLOCK_LINES: usize = 500;
+
+/// 行単位でメッセージを送受信する接続
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Self> {
+        // 短いメッセージを1つずつやりとりするので、まとめて送るのを待たない
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        Ok(Self { reader: BufReader::new(stream.try_clone()?), writer: stream })
+    }
+
+    fn send(&mut self, line: &str) -> Result<()> {
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// 複数行のデータを送る
+    fn send_block(&mut self, cmd: &str, text: &str) -> Result<()> {
+        let lines = text.lines().collect::<Vec<_>>();
+        let mut buf = format!("{} {}\n", cmd, lines.len());
+
+        for line in lines {
+            buf += line;
+            buf += "\n";
+        }
+
+        self.writer.write_all(buf.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// メッセージを1つ受け取る。戻り値は(コマンド, 引数)
+    fn recv(&mut self) -> Result<(String, String)> {
+        let mut line = String::new();
+
+        if self.reader.read_line(&mut line)? == 0 {
+            bail!("接続が切れた");
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+
+        Ok((cmd.to_string(), arg.to_string()))
+    }
+
+    /// cmdのメッセージを受け取る。違うコマンドならエラー
+    fn expect(&mut self, cmd: &str) -> Result<String> {
+        let (c, arg) = self.recv()?;
+
+        if c != cmd {
+            bail!("{}を待っていたが{}を受け取った", cmd, c);
+        }
+
+        Ok(arg)
+    }
+
+    /// send_blockで送られた、num_linesの引数に続くデータを受け取る。行数が多すぎれば読まずにエラー
+    fn recv_block(&mut self, num_lines: &str) -> Result<String> {
+        let n = num_lines.parse::<usize>().ok().filter(|n| *n <= MAX_BLOCK_LINES)
+            .ok_or_else(|| anyhow!("行数が正しくない: {}", num_lines))?;
+        let mut text = String::new();
+
+        for _ in 0..n {
+            if self.reader.read_line(&mut text)? == 0 {
+                bail!("接続が切れた");
+            }
+        }
+
+        Ok(text)
+    }
+
+    fn send_state(&mut self, state: &State) -> Result<()> {
+        let text = toml::to_string(&ScenarioToml::from_state(state))?;
+        self.send_block("state", &text)
+    }
+}
+
+/// ホスト側で、クライアントのプレイヤーの代わりになる戦略。
+/// 通信に失敗したら、それ以降は問い合わせずにノーマルアタックを続け、エラーを残す。
+struct RemoteStrategy {
+    conn: Rc<RefCell<Connection>>,
+    error: Rc<RefCell<Option<anyhow::Error>>>,
+}
+
+impl RemoteStrategy {
+    /// クライアントに状態と質問を送って答えを受け取る
+    fn ask<T>(&mut self, state: &State, question: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+        if self.error.borrow().is_some() {
+            return None;
+        }
+
+        let cmd = question.split(' ').next().unwrap();
+        let mut conn = self.conn.borrow_mut();
+
+        let result = conn.send_state(state)
+            .and_then(|_| conn.send(question))
+            .and_then(|_| conn.expect(cmd))
+            .and_then(|arg| parse(&arg).ok_or_else(|| anyhow!("{}の答えが正しくない: {}", cmd, arg)));
+
+        match result {
+            Ok(v) => Some(v),
+            Err(err) => {
+                *self.error.borrow_mut() = Some(err);
+                None
+            },
+        }
+    }
+}
+
+impl BattleStrategy for RemoteStrategy {
+    fn name(&self) -> &'static str {
+        "remote"
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, _rng: &mut BattleRng) -> Action {
+        let p = state.player(player_i);
+
+        // 硬直中は何を選んでも変わらないので聞かない
+        if p.in_fast_move || p.poke().is_faint() {
+            return Action::FastMove;
+        }
+
+        self.ask(state, "action", |s| s.parse().ok()).unwrap_or(Action::FastMove)
+    }
+
+    fn shield(&mut self, state: &State, _player_i: usize, mv: &'static ChargeMove, _rng: &mut BattleRng) -> bool {
+        self.ask(state, &format!("shield {}", mv.name()), |s| match s {
+            "y" => Some(true),
+            "n" => Some(false),
+            _ => None,
+        }).unwrap_or(true)
+    }
+
+    fn switch(&mut self, state: &State, _player_i: usize, _rng: &mut BattleRng) -> usize {
+        // 番号が正しくなければバトル側で交代先を選ぶ
+        self.ask(state, "switch", |s| s.parse().ok()).unwrap_or(0)
+    }
+}
+
+/// ログを書き出すときの名前の幅
+fn log_width(state: &State) -> usize {
+    state.player0.team.iter().chain(state.player1.team.iter())
+        .map(|p| jp_width(p.name())).max().unwrap_or(0)
+}
+
+/// クライアントからの接続を受けてrulesでバトルする。自分はプレイヤー0で、teamは(名前, ポケモン)。strategyが行動を決める。
+/// クライアントのチームはcupで使えるポケモンでなければ断る。cupがNoneなら制限しない。
+/// ルールは状態といっしょにクライアントに送る。各ターンのログをoutputに書き出し、終わったバトルを返す。
+pub fn host(stream: TcpStream, team: (String, Vec<Pokemon>), cup: Option<&Cup>, strategy: Box<dyn BattleStrategy>,
+            rules: BattleRules, seed: Option<u64>, output: &mut dyn Write) -> Result<Battle> {
+    let mut conn = Connection::new(stream)?;
+
+    let client_name = conn.expect("hello")?;
+    let num_lines = conn.expect("team")?;
+    let client_team = match conn.recv_block(&num_lines).and_then(|text| load_pokemons(&mut Cursor::new(text))) {
+        Ok(team) => team,
+        Err(err) => {
+            conn.send(&format!("error チームを受け取れない: {}", err))?;
+            return Err(err);
+        },
+    };
+
+    let team_size = rules.team_size;
+
+    if client_team.is_empty() || client_team.len() > team_size {
+        conn.send(&format!("error ポケモンの数は1～{}匹: {}", team_size, client_team.len()))?;
+        bail!("{}のポケモンの数が正しくない: {}", client_name, client_team.len());
+    }
+
+    // ホストのチームと同じく、cupで使えないポケモンと個体値が確定していないポケモンは出せない
+    for poke in &client_team {
+        let reason = match cup.and_then(|cup| cup.ineligible_reason(poke).map(|reason| format!("{}で使えない({})", cup.name, reason))) {
+            Some(reason) => Some(reason),
+            None if !poke.is_ivs_fixed() => Some(String::from("個体値が確定していない")),
+            None => None,
+        };
+
+        if let Some(reason) = reason {
+            conn.send(&format!("error {}は{}", poke.name(), reason))?;
+            bail!("{}の{}は{}", client_name, poke.name(), reason);
+        }
+    }
+
+    conn.send("start 1")?;
+
+    let conn = Rc::new(RefCell::new(conn));
+    let error = Rc::new(RefCell::new(None));
+
+    let mut battle = Battle::new(team.0, team.1, client_name, client_team);
+    battle.set_rules(rules);
+    battle.set_strategy(0, strategy);
+    battle.set_strategy(1, Box::new(RemoteStrategy { conn: conn.clone(), error: error.clone() }));
+    if let Some(seed) = seed {
+        battle.set_seed(seed);
+    }
+
+    let width = log_width(battle.state());
+
+    writeln!(output, "{} vs {}, seed = {}", battle.state().player0.name, battle.state().player1.name, battle.seed)?;
+
+    loop {
+        let actions = battle.next_actions();
+        let cont = battle.do_action(actions);
+
+        if let Some(err) = error.borrow_mut().take() {
+            return Err(err);
+        }
+
+        let log = battle.logs.last().unwrap().format(width);
+        writeln!(output, "{}", log)?;
+        conn.borrow_mut().send(&format!("log {}", log))?;
+
+        if !cont {
+            break;
+        }
+    }
+
+    let mut conn = conn.borrow_mut();
+    conn.send_state(battle.state())?;
+
+    match battle.state().phase {
+        Phase::GameOver(i) => conn.send(&format!("end gameover {}", i))?,
+        Phase::TimeOver(i) => conn.send(&format!("end timeover {}", i))?,
+        Phase::Neutral => unreachable!(),
+    }
+
+    Ok(battle)
+}
+
+/// ホストに接続してバトルする。strategyが自分の行動を決める。
+/// 各ターンのログをoutputに書き出し、バトルが終わったときの状態を返す。
+pub fn join(stream: TcpStream, name: &str, team: &Vec<Pokemon>, strategy: &mut dyn BattleStrategy,
+            output: &mut dyn Write) -> Result<State> {
+    let mut conn = Connection::new(stream)?;
+    let mut rng = BattleRng::seed_from_u64(rand::random());
+
+    let mut buf = vec![];
+    save_pokemons(&mut buf, team)?;
+
+    conn.send(&format!("hello {}", name))?;
+    conn.send_block("team", &String::from_utf8(buf)?)?;
+
+    let player_i = match conn.recv()? {
+        (cmd, arg) if cmd == "start" => arg.parse::<usize>().ok().filter(|i| *i < 2).ok_or_else(|| anyhow!("プレイヤー番号が正しくない: {}", arg))?,
+        (cmd, arg) if cmd == "error" => bail!("{}", arg),
+        (cmd, _) => bail!("startを待っていたが{}を受け取った", cmd),
+    };
+
+    let mut state = None;
+
+    loop {
+        let (cmd, arg) = conn.recv()?;
+
+        match cmd.as_str() {
+            "state" => {
+                let data: ScenarioToml = toml::from_str(&conn.recv_block(&arg)?)?;
+                state = Some(data.to_state()?);
+            },
+
+            "log" => writeln!(output, "{}", arg)?,
+
+            "end" => {
+                let mut state = state.ok_or_else(|| anyhow!("状態を受け取っていない"))?;
+                let winner = arg.split_once(' ').and_then(|(_, i)| i.parse::<u8>().ok()).filter(|i| *i <= 2);
+
+                state.phase = match (arg.split(' ').next(), winner) {
+                    (Some("gameover"), Some(i)) => Phase::GameOver(i),
+                    (Some("timeover"), Some(i)) => Phase::TimeOver(i),
+                    _ => bail!("endの引数が正しくない: {}", arg),
+                };
+
+                return Ok(state);
+            },
+
+            "error" => bail!("{}", arg),
+
+            "action" | "shield" | "switch" => {
+                let state = state.as_ref().ok_or_else(|| anyhow!("状態を受け取っていない"))?;
+
+                let answer = match cmd.as_str() {
+                    "action" => strategy.action(state, player_i, &mut rng).to_string(),
+                    "shield" => {
+                        let mv = charge_move_by_name(&arg).ok_or_else(|| anyhow!("存在しない技: {}", arg))?;
+                        if strategy.shield(state, player_i, mv, &mut rng) { "y" } else { "n" }.to_string()
+                    },
+                    _ => strategy.switch(state, player_i, &mut rng).to_string(),
+                };
+
+                conn.send(&format!("{} {}", cmd, answer))?;
+            },
+
+            _ => bail!("不明なコマンド: {}", cmd),
+        }
+    }
+}
+
+#[test]
+fn test_net_battle() {
+    use std::net::TcpListener;
+    use std::thread;
+    use crate::strategy::StrategyKind;
+
+    let team = || vec![
+        Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap(),
+        Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap(),
+    ];
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let rules = BattleRules::by_name("noshield").unwrap();
+
+    let host_thread = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let battle = host(stream, (String::from("host"), team()), None, StrategyKind::Greedy.create(), rules, Some(3), &mut std::io::sink()).unwrap();
+        (battle.state().clone(), battle.logs.len())
+    });
+
+    let mut strategy = StrategyKind::Smart.create();
+    let mut logs = vec![];
+    let stream = TcpStream::connect(addr).unwrap();
+    let state = join(stream, "client", &team(), strategy.as_mut(), &mut logs).unwrap();
+
+    let (host_state, num_logs) = host_thread.join().unwrap();
+
+    // クライアントにもホストと同じ結果とログが届く
+    assert_eq!(state.phase, host_state.phase);
+    assert_eq!(state.player1.name, "client");
+    // ホストのルールでバトルして、クライアントにも届く
+    assert_eq!(host_state.rules, rules);
+    assert_eq!(state.rules, rules);
+    assert_eq!(state.player1.num_shields, 0);
+    assert_eq!(state.player0.num_remains(), host_state.player0.num_remains());
+    assert_eq!(state.player1.num_remains(), host_state.player1.num_remains());
+    assert_eq!(String::from_utf8(logs).unwrap().lines().count(), num_logs);
+}
+
+#[test]
+fn test_net_reject_team() {
+    use std::net::TcpListener;
+    use std::thread;
+    use crate::strategy::StrategyKind;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let host_thread = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let team = vec![Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap()];
+        host(stream, (String::from("host"), team), None, StrategyKind::Greedy.create(), BattleRules::default(), None, &mut std::io::sink()).is_err()
+    });
+
+    // 行数が多すぎるチームは読まずに断る
+    let mut conn = Connection::new(TcpStream::connect(addr).unwrap()).unwrap();
+    conn.send("hello client").unwrap();
+    conn.send(&format!("team {}", MAX_BLOCK_LINES + 1)).unwrap();
+
+    assert_eq!(conn.recv().unwrap().0, "error");
+    assert!(host_thread.join().unwrap());
+}
+
+#[test]
+fn test_net_reject_ineligible() {
+    use std::net::TcpListener;
+    use std::thread;
+    use crate::cup::Cup;
+    use crate::league::League;
+    use crate::strategy::StrategyKind;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let host_thread = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let team = vec![Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap()];
+        let cup = Cup::open(League::GREAT);
+        host(stream, (String::from("host"), team), Some(&cup), StrategyKind::Greedy.create(), BattleRules::default(), None, &mut std::io::sink()).is_err()
+    });
+
+    // ホストのカップのCPの上限を超えるポケモンはバトルに出せない
+    let team = vec![Pokemon::new("ナマズン", Some(40.0), (15, 15, 15), "みずでっぽう", "どろばくだん", None, 0).unwrap()];
+    assert!(team[0].cp() > 1500);
+
+    let mut strategy = StrategyKind::Greedy.create();
+    let err = join(TcpStream::connect(addr).unwrap(), "client", &team, strategy.as_mut(), &mut std::io::sink()).unwrap_err();
+
+    assert!(err.to_string().contains("ナマズン"), "{}", err);
+    assert!(host_thread.join().unwrap());
+}
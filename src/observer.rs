@@ -0,0 +1,168 @@
+//! バトル中の出来事を受け取るオブザーバー
+//! エンジンは出来事が起きるたびにBattleObserver::on_eventを呼ぶ。
+//! ログの表示や統計、可視化などはエンジンを変更せずにオブザーバーとして作れる。
+
+use std::fmt;
+use std::io::Write;
+
+use crate::battle::{State, Action};
+use crate::moves::{FastMove, ChargeMove, Buff};
+
+/// バトル中に起きた出来事。player_iはその出来事を起こした(または受けた)プレイヤー
+#[derive(Debug, Clone, PartialEq)]
+pub enum BattleEvent {
+    /// ノーマルアタックのダメージが入った
+    FastMove { player_i: usize, mv: &'static FastMove, damage: i32 },
+
+    /// スペシャルアタックを使った。shieldedなら相手がシールドを使った
+    ChargeMove { player_i: usize, mv: &'static ChargeMove, damage: i32, shielded: bool },
+
+    /// シールドを使った
+    Shield { player_i: usize },
+
+    /// スペシャルアタックmvの効果でステータス変化が起きた
+    Buff { player_i: usize, mv: &'static ChargeMove, buff: Buff },
+
+    /// ポケモンが瀕死になった
+    Faint { player_i: usize, poke: &'static str },
+
+    /// ポケモンを交代した。forcedなら瀕死になったポケモンの代わり
+    Switch { player_i: usize, poke: &'static str, forced: bool },
+
+    /// 攻撃が同じでスペシャルアタックの先攻をランダムに決めた(CMPの引き分け)。firstが先攻
+    CmpTie { first: usize },
+
+    /// 行動を実行できなかった(エネルギー不足など)
+    ActionIgnored { player_i: usize, action: Action },
+
+    /// どちらかのポケモンがすべて瀕死になった。winnerは勝者(2なら引き分け)
+    GameOver { winner: u8 },
+
+    /// 制限時間になった。winnerは勝者(2なら引き分け)
+    TimeOver { winner: u8 },
+}
+
+impl fmt::Display for BattleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            BattleEvent::FastMove { player_i, mv, damage } => write!(f, "[{}] {} ダメージ {}", player_i, mv.name(), damage),
+            BattleEvent::ChargeMove { player_i, mv, damage, shielded } =>
+                write!(f, "[{}] *{}* ダメージ {}{}", player_i, mv.name(), damage, if *shielded { " (シールド)" } else { "" }),
+            BattleEvent::Shield { player_i } => write!(f, "[{}] シールド", player_i),
+            BattleEvent::Buff { player_i, mv, buff } => write!(f, "[{}] {}のステータス変化 {:?}", player_i, mv.name(), buff),
+            BattleEvent::Faint { player_i, poke } => write!(f, "[{}] {}は瀕死", player_i, poke),
+            BattleEvent::Switch { player_i, poke, forced } =>
+                write!(f, "[{}] 交代 {}{}", player_i, poke, if *forced { " (瀕死)" } else { "" }),
+            BattleEvent::CmpTie { first } => write!(f, "CMPが同じ。[{}]が先攻", first),
+            BattleEvent::ActionIgnored { player_i, action } => write!(f, "[{}] {}を実行できない", player_i, action),
+            BattleEvent::GameOver { winner: 2 } => write!(f, "ゲームオーバー。引き分け"),
+            BattleEvent::GameOver { winner } => write!(f, "ゲームオーバー。[{}]の勝ち", winner),
+            BattleEvent::TimeOver { winner: 2 } => write!(f, "時間切れ。引き分け"),
+            BattleEvent::TimeOver { winner } => write!(f, "時間切れ。[{}]の勝ち", winner),
+        }
+    }
+}
+
+/// バトル中の出来事を受け取る。stateは出来事が起きた直後の状態
+pub trait BattleObserver {
+    fn on_event(&mut self, state: &State, event: &BattleEvent);
+}
+
+/// 何もしないオブザーバー
+pub struct NullObserver;
+
+impl BattleObserver for NullObserver {
+    fn on_event(&mut self, _state: &State, _event: &BattleEvent) {}
+}
+
+/// 登録されたすべてのオブザーバーに知らせる
+impl BattleObserver for Vec<Box<dyn BattleObserver>> {
+    fn on_event(&mut self, state: &State, event: &BattleEvent) {
+        for observer in self.iter_mut() {
+            observer.on_event(state, event);
+        }
+    }
+}
+
+/// 出来事をターン数と一緒に1行ずつ書き出す
+pub struct PrintObserver<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PrintObserver<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> BattleObserver for PrintObserver<W> {
+    fn on_event(&mut self, state: &State, event: &BattleEvent) {
+        let _ = writeln!(self.writer, "{:>3} {}", state.turn, event);
+    }
+}
+
+#[test]
+fn test_battle_observer() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use crate::battle::{Battle, BattleRules, PlayerOptions};
+    use crate::pokemon::Pokemon;
+
+    /// 受け取った出来事を記録するだけのオブザーバー
+    struct Recorder(Rc<RefCell<Vec<BattleEvent>>>);
+
+    impl BattleObserver for Recorder {
+        fn on_event(&mut self, _state: &State, event: &BattleEvent) {
+            self.0.borrow_mut().push(event.clone());
+        }
+    }
+
+    fn new_battle() -> (Battle, Rc<RefCell<Vec<BattleEvent>>>) {
+        let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+        let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+        let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+        let mut battle = Battle::new(String::from("p0"), vec![p0, p1.clone()], String::from("p1"), vec![p2, p1]);
+        battle.set_seed(0);
+
+        let events = Rc::new(RefCell::new(vec![]));
+        battle.add_observer(Box::new(Recorder(events.clone())));
+
+        (battle, events)
+    }
+
+    let (mut battle, events) = new_battle();
+    battle.start();
+
+    let events = events.borrow();
+    assert!(events.iter().any(|e| matches!(e, BattleEvent::FastMove { .. })));
+    assert!(events.iter().any(|e| matches!(e, BattleEvent::ChargeMove { .. })));
+    assert!(events.iter().any(|e| matches!(e, BattleEvent::Faint { .. })));
+    assert!(events.iter().any(|e| matches!(e, BattleEvent::Switch { forced: true, .. })));
+    assert!(matches!(events.last(), Some(BattleEvent::GameOver { .. })));
+
+    // 瀕死の数はログと一致する
+    let num_faints = battle.logs.iter().flat_map(|log| log.events0.iter().chain(log.events1.iter())).filter(|e| matches!(e, crate::battle::Event::Faint)).count();
+    assert_eq!(events.iter().filter(|e| matches!(e, BattleEvent::Faint { .. })).count(), num_faints);
+
+    // 制限時間
+    let (mut battle, events) = new_battle();
+    battle.set_rules(BattleRules { limit_turn: 10, ..BattleRules::default() });
+    battle.start();
+    assert!(matches!(events.borrow().last(), Some(BattleEvent::TimeOver { .. })));
+
+    // 同じポケモン同士で同時にスペシャルアタックを使うとCMPが同じになる
+    let poke = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+    let mut battle = Battle::new(String::from("p0"), vec![poke.clone()], String::from("p1"), vec![poke]);
+    let options = PlayerOptions { energy: 100, ..PlayerOptions::default() };
+    battle.set_player_options(0, &options);
+    battle.set_player_options(1, &options);
+
+    let events = Rc::new(RefCell::new(vec![]));
+    battle.add_observer(Box::new(Recorder(events.clone())));
+    battle.do_action([Action::ChargeMove(0), Action::ChargeMove(0)]);
+
+    let events = events.borrow();
+    assert!(matches!(events.first(), Some(BattleEvent::CmpTie { .. })));
+    assert_eq!(events.iter().filter(|e| matches!(e, BattleEvent::ChargeMove { .. })).count(), 2);
+}
@@ -14,9 +14,10 @@ use crate::types::{Type, TYPES};
 use crate::moves::{FastMove, ChargeMove, Buff, fast_move_by_name, charge_move_by_name, fast_move_by_no, charge_move_by_no};
 use crate::cpm::cpm;
 use crate::battle::rank_mul;
-use crate::utils::jp_fixed_width_string;
+use crate::utils::{jp_fixed_width_string, format_range};
+use crate::appraisal::format_ivs_range;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Pokemon {
     dict: &'static Pokepedia,
 
@@ -25,6 +26,9 @@ pub struct Pokemon {
     // 個体値
     ivs: IVs,
 
+    // 鑑定から絞り込んだ個体値の候補(ivsを含む)。空なら個体値は確定している
+    ivs_candidates: Vec<IVs>,
+
     // 技
     fast_move: &'static FastMove,
     charge_move1: &'static ChargeMove,
@@ -32,7 +36,7 @@ pub struct Pokemon {
 }
 
 /// ステータス
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Stats {
     pub attack: f64,
     pub defense: f64,
@@ -70,7 +74,7 @@ impl Stats {
     /// SCPは攻撃力・防御力・耐久性をバランスよく表した指標。
     /// トレーナーバトルなど1対1の対戦で参考となる。
     pub fn calc_scp(&self) -> i32 {
-        let v = self.attack * self.defense * self.stamina.floor();
+        let v = self.calc_stat_product();
         let scp = (v.powf(2.0/3.0) / 10.0) as i32;
 
         if scp < 10 {
@@ -80,6 +84,11 @@ impl Stats {
         }
     }
 
+    /// ステータス積(攻撃 × 防御 × HP)を計算して返す。PvPの個体値ランクの基準。
+    pub fn calc_stat_product(&self) -> f64 {
+        self.attack * self.defense * self.stamina.floor()
+    }
+
     /// DCP(Defensive Combat Power, 防御的戦闘力)を計算して返す。
     /// DCPは独自の指標でゲームでは表示されることはない。
     /// DCPは防御力と耐久性を重視した指標となる。
@@ -162,7 +171,7 @@ impl Pokemon {
                 charge_move2: Option<&'static ChargeMove>
                 ) -> Self {
 
-        Pokemon { dict, lv, ivs, fast_move, charge_move1, charge_move2 }
+        Pokemon { dict, lv, ivs, ivs_candidates: vec![], fast_move, charge_move1, charge_move2 }
     }
 
     pub fn new_by_no(no: &str, pokemon_lv: Option<f32>, ivs_tuple: (i32, i32, i32),
@@ -293,7 +302,7 @@ impl Pokemon {
             }
         };
 
-        Ok(Pokemon { dict, lv, ivs, fast_move, charge_move1, charge_move2 })
+        Ok(Pokemon { dict, lv, ivs, ivs_candidates: vec![], fast_move, charge_move1, charge_move2 })
     }
 
     pub fn dict(&self) -> &'static Pokepedia {
@@ -320,7 +329,21 @@ impl Pokemon {
         self.dict.base_stats()
     }
 
+    /// CPを変えてポケモンレベルを計算しなおす。個体値の候補はcpにならないものを除く
     pub fn set_cp(&mut self, cp: i32) -> bool {
+        if !self.ivs_candidates.is_empty() {
+            let candidates = self.ivs_candidates.iter().copied()
+                .filter(|ivs| calc_lv(self.dict, cp, *ivs).is_some())
+                .collect::<Vec<_>>();
+
+            if candidates.is_empty() {
+                return false;
+            }
+
+            self.ivs = candidates[0];
+            self.set_ivs_candidates(candidates);
+        }
+
         match calc_lv(self.dict, cp, self.ivs) {
             None => false,
             Some(lv) => {
@@ -346,6 +369,58 @@ impl Pokemon {
         &mut self.ivs
     }
 
+    /// 個体値の候補。個体値が確定していればivsだけ
+    pub fn ivs_candidates(&self) -> &[IVs] {
+        if self.ivs_candidates.is_empty() {
+            std::slice::from_ref(&self.ivs)
+        } else {
+            &self.ivs_candidates
+        }
+    }
+
+    /// 個体値の候補を設定する。候補はすべて今のCPになるもので、ivsを含むこと。
+    /// 候補が1つ以下なら個体値は確定しているとする
+    pub fn set_ivs_candidates(&mut self, candidates: Vec<IVs>) {
+        self.ivs_candidates = if candidates.len() > 1 { candidates } else { vec![] };
+    }
+
+    /// 個体値が確定しているか
+    pub fn is_ivs_fixed(&self) -> bool {
+        self.ivs_candidates.is_empty()
+    }
+
+    /// 個体値を候補のどれかに決め直す。正確な個体値なら1つだけ渡す。
+    /// CPは変えず、今のCPにならない候補は除く。候補が残らなければfalse
+    pub fn replace_ivs(&mut self, candidates: Vec<IVs>) -> bool {
+        let cp = self.cp();
+        let candidates = candidates.into_iter().filter(|ivs| calc_lv(self.dict, cp, *ivs).is_some()).collect::<Vec<_>>();
+
+        match candidates.first().and_then(|ivs| calc_lv(self.dict, cp, *ivs)) {
+            None => false,
+            Some(lv) => {
+                self.ivs = candidates[0];
+                self.lv = lv;
+                self.set_ivs_candidates(candidates);
+                true
+            },
+        }
+    }
+
+    /// 個体値の候補それぞれのポケモン。CPは同じでポケモンレベルは候補ごとに違う。
+    /// 個体値が確定していれば自分だけ
+    pub fn candidate_pokemons(&self) -> Vec<Pokemon> {
+        if self.is_ivs_fixed() {
+            return vec![self.clone()];
+        }
+
+        let cp = self.cp();
+
+        self.ivs_candidates().iter().filter_map(|ivs| {
+            let lv = calc_lv(self.dict, cp, *ivs)?;
+            Some(Pokemon { lv, ivs: *ivs, ivs_candidates: vec![], ..self.clone() })
+        }).collect()
+    }
+
     pub fn fast_move(&self) -> &'static FastMove {
         self.fast_move
     }
@@ -412,17 +487,20 @@ impl Pokemon {
         }
     }
 
+    /// 個体値が確定していなければ、候補それぞれの値を範囲で表す
     pub fn format(&self, width: usize) -> String {
-        let stats = self.stats();
-        let name = jp_fixed_width_string(self.name(), width);
+        format_candidates(&self.candidate_pokemons(), width)
+    }
 
-        let fm_desc = self.fast_move_desc();
-        let cm1_desc = self.charge_move1_desc();
-        let cm2_desc = self.charge_move2_desc();
+    /// 個体値の候補それぞれのECPの範囲を"1520-1540"のように表す
+    pub fn format_ecp(&self, opponent: Option<&Pokemon>, custom_types: Option<Vec<Type>>, num_shields: i32) -> String {
+        let values = self.candidate_pokemons().iter().map(|p| p.ecp(opponent, custom_types.clone(), num_shields)).collect::<Vec<_>>();
+        format_range(values, |v| format!("{:>4}", v))
+    }
 
-        format!("{} CP {:>4} SCP {:>4} ECP1 {:>4} Lv {:>4.1} IVs({:>2}, {:>2}, {:>2}) Stats({:>5.1}, {:>5.1}, {:>3}) {} | {} | {}",
-                 name, self.cp(), self.scp(), self.avg_ecp(1), self.lv, self.ivs.attack, self.ivs.defense, self.ivs.stamina,
-                 stats.attack, stats.defense, self.hp(), fm_desc, cm1_desc, cm2_desc)
+    /// 個体値の候補それぞれのSCPの範囲を"1520-1540"のように表す
+    pub fn format_scp(&self) -> String {
+        format_range(self.candidate_pokemons().iter().map(|p| p.scp()), |v| v.to_string())
     }
 
     /// 1ターンあたりの平均的なわざの威力を計算する
@@ -446,6 +524,7 @@ impl Pokemon {
     ///
     /// * 自分の防御のステータス変化は相手の防御への逆ステータス変化とする。
     ///   相手の攻撃のステータス変化は自分の攻撃への逆ステータス変化とする。
+    #[allow(clippy::manual_clamp)]
     pub fn calc_power_per_turn(&self, opponent: Option<&Pokemon>, custom_types: Option<Vec<Type>>, mut num_shields: i32) -> (f64, i32) {
         let types;
         let defender;
@@ -599,7 +678,7 @@ impl Pokemon {
                         continue;
                     }
 
-                    v.push(Pokemon { dict: self.dict, lv: self.lv, ivs: self.ivs, fast_move, charge_move1, charge_move2: Some(charge_move2) });
+                    v.push(Pokemon { dict: self.dict, lv: self.lv, ivs: self.ivs, ivs_candidates: self.ivs_candidates.clone(), fast_move, charge_move1, charge_move2: Some(charge_move2) });
 
                     set.insert(charge_move2.no().to_string() + charge_move1.no());
                 }
@@ -633,6 +712,24 @@ fn test_calc_power_per_turn() {
 }
 
 /// 引数として渡された種族値、CP、個体値からポケモンレベルを計算して返す。
+/// 個体値の候補それぞれのポケモンを1行で表す。名前と技は先頭のポケモンのもの。
+/// CP、SCP、ECP1、ポケモンレベル、個体値、ステータスは候補で違えば範囲で表す
+pub fn format_candidates(candidates: &[Pokemon], width: usize) -> String {
+    let p = &candidates[0];
+    let name = jp_fixed_width_string(p.name(), width);
+
+    let range_i = |f: fn(&Pokemon) -> i32, w: usize| format_range(candidates.iter().map(f), |v| format!("{:>w$}", v));
+    let range_f = |f: fn(&Pokemon) -> f64, w: usize| format_range(candidates.iter().map(f), |v| format!("{:>w$.1}", v));
+
+    let ivs = candidates.iter().map(|c| c.ivs).collect::<Vec<_>>();
+
+    format!("{} CP {} SCP {} ECP1 {} Lv {} {} Stats({}, {}, {}) {} | {} | {}",
+             name, range_i(|c| c.cp(), 4), range_i(|c| c.scp(), 4), range_i(|c| c.avg_ecp(1), 4),
+             range_f(|c| c.lv as f64, 4), format_ivs_range(&ivs),
+             range_f(|c| c.stats().attack, 5), range_f(|c| c.stats().defense, 5), range_i(|c| c.hp(), 3),
+             p.fast_move_desc(), p.charge_move1_desc(), p.charge_move2_desc())
+}
+
 pub fn calc_lv(poke: &Pokepedia, cp: i32, ivs: IVs) -> Option<f32> {
     for i in 2..=100 {
         let lv = i as f32 / 2.0;
@@ -669,6 +766,10 @@ struct PokemonToml {
     // 個体値(0～15)
     ivs: IVs,
 
+    // 鑑定から絞り込んだ個体値の候補。個体値が確定していればなし
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ivs_candidates: Option<Vec<IVs>>,
+
     // 技
     fast_move: String,
     charge_move1: String,
@@ -711,7 +812,7 @@ pub fn load_pokemons<R: Read>(reader: &mut R) -> Result<Vec<Pokemon>> {
                                 &d.fast_move, &d.charge_move1, d.charge_move2.clone(), d.cp);
 
         match poke {
-            Ok(poke) => {
+            Ok(mut poke) => {
                 if let Some(hp) = d.hp {
                     if hp != poke.hp() {
                         eprintln!("{} HPが一致しない, データ: {}, 計算結果: {}", poke.name(), hp, poke.hp());
@@ -719,6 +820,23 @@ pub fn load_pokemons<R: Read>(reader: &mut R) -> Result<Vec<Pokemon>> {
                     }
                 }
 
+                if let Some(candidates) = &d.ivs_candidates {
+                    // CPとHPが合わない候補は除く
+                    let candidates = candidates.iter().copied().filter(|ivs| {
+                        match calc_lv(poke.dict, d.cp, *ivs) {
+                            None => false,
+                            Some(lv) => d.hp.is_none() || d.hp == Some(poke.base_stats().stats(lv, *ivs).stamina.floor() as i32),
+                        }
+                    }).collect::<Vec<_>>();
+
+                    if !candidates.contains(&poke.ivs) {
+                        eprintln!("{} 個体値の候補に個体値{:?}がない", poke.name(), poke.ivs.to_tuple());
+                        continue;
+                    }
+
+                    poke.set_ivs_candidates(candidates);
+                }
+
                 pokemons.push(poke)
             },
             Err(err) => println!("{}", err),
@@ -814,7 +932,12 @@ pub fn save_pokemons<W: Write>(writer: &mut W, pokemons: &Vec<Pokemon>) -> Resul
 
         let charge_move2 = p.charge_move2.map(|mv| String::from(mv.no()));
 
-        let ptoml = PokemonToml { no, cp: p.cp(), hp: Some(p.hp()), ivs: p.ivs,
+        // HPは候補がすべて同じときだけ保存する
+        let candidates = p.candidate_pokemons();
+        let hp = if candidates.iter().all(|c| c.hp() == p.hp()) { Some(p.hp()) } else { None };
+        let ivs_candidates = if p.is_ivs_fixed() { None } else { Some(p.ivs_candidates.clone()) };
+
+        let ptoml = PokemonToml { no, cp: p.cp(), hp, ivs: p.ivs, ivs_candidates,
                       fast_move, charge_move1, charge_move2 };
 
         v.push(ptoml);
@@ -831,6 +954,50 @@ pub fn save_pokemons<W: Write>(writer: &mut W, pokemons: &Vec<Pokemon>) -> Resul
     Ok(())
 }
 
+#[test]
+fn test_ivs_candidates() {
+    use crate::appraisal::candidate_ivs;
+
+    let mut poke = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    assert!(poke.is_ivs_fixed());
+    assert_eq!(poke.ivs_candidates(), &[poke.ivs()]);
+
+    let candidates = candidate_ivs(poke.dict(), 1489, None, &"2".parse().unwrap());
+    assert!(candidates.len() > 1 && candidates.contains(&poke.ivs()));
+    poke.set_ivs_candidates(candidates.clone());
+    assert!(!poke.is_ivs_fixed());
+    assert!(poke.candidate_pokemons().iter().all(|p| p.cp() == 1489));
+
+    // 候補で違う値は範囲で表示する
+    let scps = poke.candidate_pokemons().iter().map(|p| p.scp()).collect::<Vec<_>>();
+    let scp_range = format!("{}-{}", scps.iter().min().unwrap(), scps.iter().max().unwrap());
+    assert_eq!(poke.format_scp(), scp_range);
+    assert!(poke.format(10).contains(&format!("SCP {}", scp_range)));
+
+    // 保存して読み込んでも候補は残る
+    let mut buf = vec![];
+    save_pokemons(&mut buf, &vec![poke.clone()]).unwrap();
+    let loaded = load_pokemons(&mut buf.as_slice()).unwrap();
+    assert_eq!(loaded, vec![poke.clone()]);
+
+    // 強化してCPが変わると、そのCPにならない候補は除かれる
+    let ivs = IVs::new(10, 9, 12).unwrap();
+    let cp = poke.dict().base_stats().stats(35.0, ivs).calc_cp();
+    assert!(poke.set_cp(cp));
+    assert!(poke.ivs_candidates().contains(&ivs));
+    assert!(poke.ivs_candidates().len() < candidates.len());
+    assert!(poke.candidate_pokemons().iter().all(|p| p.cp() == cp));
+    assert!(!poke.set_cp(10));
+
+    // 正確な個体値に置き換えると確定し、確定したポケモンと同じ表示になる
+    let exact = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, cp).unwrap();
+    assert!(!poke.replace_ivs(vec![IVs::new(15, 15, 15).unwrap()]));
+    assert!(poke.replace_ivs(vec![ivs]));
+    assert!(poke.is_ivs_fixed());
+    assert_eq!(poke.format(10), exact.format(10));
+    assert_eq!(poke.format_scp(), exact.scp().to_string());
+}
+
 struct PokemonItem {
     display_str: String,
     output_index: String,
@@ -848,6 +1015,7 @@ impl PokemonItem {
 }
 
 impl SkimItem for PokemonItem {
+    #[allow(mismatched_lifetime_syntaxes)]
     fn text(&self) -> Cow<str> {
         Cow::Borrowed(&self.search_str)
     }
@@ -861,6 +1029,7 @@ impl SkimItem for PokemonItem {
     }
 }
 
+#[allow(clippy::manual_ok_err)]
 pub fn skim_pokemons(pokemons: &[Pokemon], width: usize) -> Option<usize> {
     let options = SkimOptions::default();
 
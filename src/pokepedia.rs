@@ -10,7 +10,7 @@ use crate::moves::*;
 use crate::types::{Type, TYPES};
 use crate::utils::NameItem;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Pokepedia {
     no: &'static str,
     name: &'static str,
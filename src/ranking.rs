@@ -1,8 +1,17 @@
 //! ポケモンのランキングを作る
 
-use crate::pokepedia::POKEPEDIA;
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::pokepedia::{Pokepedia, POKEPEDIA};
 use crate::pokemon::Pokemon;
 use crate::index::calc_top_scp_iv_limited_by_cp;
+use crate::league::League;
+use crate::cup::Cup;
+use crate::battle::{Battle, State, Player, PlayerOptions, MS_PER_TURN, TURN_PER_SEC};
+use crate::strategy::StrategyKind;
+use crate::utils::par_map;
 
 /*
 pub fn get_high_ecp(dict: &'static Pokepedia, limit_cp: i32, limit_lv: f32)
@@ -34,11 +43,12 @@ pub fn get_top_pokemons(limit_cp: i32, limit_lv: f32) -> Vec<Pokemon> {
 }
 */
 
-pub fn scp_ranking(limit_cp: i32, limit_lv: f32) -> Vec<Pokemon> {
+/// cupに出せるポケモンを一番SCPが高くなる個体値で作り、SCPの高い順に並べる
+pub fn scp_ranking(cup: &Cup) -> Vec<Pokemon> {
     let mut v = vec![];
 
-    for p in &POKEPEDIA {
-        if let Some((_, lv, ivs)) = calc_top_scp_iv_limited_by_cp(limit_cp, limit_lv, p) {
+    for p in POKEPEDIA.iter().filter(|p| cup.allows_dict(p)) {
+        if let Some((_, lv, ivs)) = calc_top_scp_iv_limited_by_cp(cup.league, p, 0) {
             let poke = Pokemon::raw_new(p, lv, ivs, p.fast_moves()[0], p.charge_moves()[0], None);
             v.push(poke);
         }
@@ -48,3 +58,325 @@ pub fn scp_ranking(limit_cp: i32, limit_lv: f32) -> Vec<Pokemon> {
 
     v
 }
+
+/// 一番SCPが高くなる個体値で、平均ECPが一番高い技の組み合わせのポケモンを作る。
+/// ポケモンレベル1.0でもleagueのCPの上限を超える場合は、Noneを返す。
+pub fn league_pokemon(dict: &'static Pokepedia, league: League) -> Option<Pokemon> {
+    let (_, lv, ivs) = calc_top_scp_iv_limited_by_cp(league, dict, 0)?;
+    let poke = Pokemon::raw_new(dict, lv, ivs, dict.fast_moves()[0], dict.charge_moves()[0], None);
+
+    let mut perm = poke.move_perm();
+
+    if perm.is_empty() {  // スペシャルアタックが1つしかない
+        perm = dict.fast_moves().iter()
+            .map(|fm| Pokemon::raw_new(dict, lv, ivs, fm, dict.charge_moves()[0], None))
+            .collect();
+    }
+
+    perm.into_iter().max_by_key(|p| p.avg_ecp(1))
+}
+
+#[test]
+fn test_league_pokemon() {
+    let koko = crate::pokepedia::pokepedia_by_name("ココロモリ").unwrap();
+    let p = league_pokemon(koko, League::GREAT).unwrap();
+
+    assert!(p.cp() <= 1500);
+    assert_eq!(p.lv(), 38.0);
+    assert!(p.charge_move2().is_some());
+}
+
+/// cupに出せるすべてのポケモンを作る
+pub fn cup_pokemons(cup: &Cup) -> Vec<Pokemon> {
+    let dicts = POKEPEDIA.iter().filter(|dict| cup.allows_dict(dict)).collect::<Vec<_>>();
+
+    par_map(&dicts, |dict| league_pokemon(dict, cup.league))
+        .into_iter().flatten().collect()
+}
+
+/// シールドの数の組み合わせ。[自分のシールド, 相手のシールド]
+pub const SHIELD_SCENARIOS: [[i32; 2]; 3] = [[0, 0], [1, 1], [2, 2]];
+
+/// 役割ごとのバトルの条件
+pub struct RoleScenario {
+    pub name: &'static str,
+    pub options: [PlayerOptions; 2],  // [自分, 相手]の開始時の条件
+    pub head_start: bool,  // 相手のノーマルアタックが1ターン前から始まっている
+}
+
+impl RoleScenario {
+    /// pokeとopponentをこの条件で戦わせる、開始前のバトル
+    pub fn battle(&self, poke: &Pokemon, opponent: &Pokemon, kind: StrategyKind, seed: u64) -> Battle {
+        let mut state = State::new(Player::new(String::from("player0"), vec![poke.clone()]),
+                                   Player::new(String::from("player1"), vec![opponent.clone()]));
+        self.options[0].apply(&mut state.player0);
+        self.options[1].apply(&mut state.player1);
+
+        // 自分が交代で出てくる間に相手はノーマルアタックを始めているので、相手の技が先に入る
+        if self.head_start {
+            let p = &mut state.player1;
+            p.in_fast_move = true;
+            p.dur_turns = p.poke().fast_move().turns() - 1;
+        }
+
+        let mut battle = Battle::from_state(state);
+        battle.set_strategy(0, kind.create());
+        battle.set_strategy(1, kind.create());
+        battle.set_seed(seed);
+
+        battle
+    }
+
+    /// pokeとopponentをこの条件で戦わせたときのpokeのバトルレーティング
+    pub fn rating(&self, poke: &Pokemon, opponent: &Pokemon, kind: StrategyKind, seed: u64) -> i32 {
+        let mut battle = self.battle(poke, opponent, kind, seed);
+
+        battle.start();
+        battle.rating(0)
+    }
+}
+
+const fn start_options(num_shields: i32) -> PlayerOptions {
+    PlayerOptions { num_shields: Some(num_shields), energy: 0, hp_percent: 100, buff: (0, 0), switch_ms: 0 }
+}
+
+/// 役割ごとの条件。
+/// lead: 先発、closer: 最後の1匹、switch: 相手がエネルギーをためている場面で交代して出る、attacker: シールドの数で不利
+pub const ROLE_SCENARIOS: [RoleScenario; 4] = [
+    RoleScenario { name: "lead", options: [start_options(2), start_options(2)], head_start: false },
+    // 相手はノーマルアタック6ターン分ほどのエネルギーをためていて、1ターン先に動いている。
+    // 自分は交代したばかりなので、交代できるようになるまで待たなければならない
+    RoleScenario {
+        name: "switch",
+        options: [PlayerOptions { switch_ms: 60 * TURN_PER_SEC * MS_PER_TURN, ..start_options(1) },
+                  PlayerOptions { energy: 20, ..start_options(1) }],
+        head_start: true,
+    },
+    RoleScenario { name: "closer", options: [start_options(0), start_options(0)], head_start: false },
+    RoleScenario { name: "attacker", options: [start_options(0), start_options(1)], head_start: false },
+];
+
+/// pokeのシナリオごとの平均レーティングをopponentsとの対戦から求める。
+/// 総当たりのランキングを作らずに、手持ちのポケモンの役割を調べるのに使う
+pub fn role_scores(poke: &Pokemon, opponents: &[Pokemon], kind: StrategyKind) -> Vec<f64> {
+    let n = opponents.len();
+
+    ROLE_SCENARIOS.iter().enumerate().map(|(scenario_i, scenario)| {
+        let sum = opponents.iter().enumerate()
+            .map(|(j, opponent)| scenario.rating(poke, opponent, kind, (scenario_i * n * n + j) as u64))
+            .sum::<i32>();
+
+        if n == 0 { 500.0 } else { sum as f64 / n as f64 }
+    }).collect()
+}
+
+/// 総当たりの1対1のバトルで作ったランキング
+pub struct BattleRanking {
+    pub pokemons: Vec<Pokemon>,
+    pub cup: Cup,  // pokemonsを選んだカップ
+    pub kind: StrategyKind,  // 両方のプレイヤーが使う戦略
+    pub ratings: Vec<Vec<Vec<i32>>>,  // バトルレーティング。ratings[シナリオ][i][j]はiのjに対するレーティング
+    pub scores: Vec<f64>,  // 総合スコア。全シナリオのレーティングの平均
+}
+
+impl BattleRanking {
+    /// cupに出せるすべてのポケモンでランキングを作る
+    pub fn for_cup(cup: Cup, kind: StrategyKind) -> Self {
+        Self::new(cup_pokemons(&cup), cup, kind)
+    }
+
+    /// cupのpokemonsの総当たりのバトルをSHIELD_SCENARIOSのシナリオごとに行う。
+    /// 両方のプレイヤーが同じ戦略kindを使う。
+    /// バトルの乱数の種は組み合わせごとに決まっているので、同じ引数なら同じ結果になる。
+    pub fn new(pokemons: Vec<Pokemon>, cup: Cup, kind: StrategyKind) -> Self {
+        let n = pokemons.len();
+        let rows = (0..n).collect::<Vec<_>>();
+
+        // iとjのバトルのレーティングを足すと1000になるので、i < jの組み合わせだけバトルする
+        let upper = par_map(&rows, |i| {
+            let i = *i;
+
+            SHIELD_SCENARIOS.iter().enumerate().map(|(scenario_i, shields)| {
+                ((i + 1)..n).map(|j| {
+                    let mut battle = Battle::new(String::from("player0"), vec![pokemons[i].clone()],
+                                                 String::from("player1"), vec![pokemons[j].clone()]);
+                    battle.set_strategy(0, kind.create());
+                    battle.set_strategy(1, kind.create());
+                    battle.set_player_options(0, &start_options(shields[0]));
+                    battle.set_player_options(1, &start_options(shields[1]));
+                    battle.set_seed((scenario_i * n * n + i * n + j) as u64);
+                    battle.start();
+
+                    battle.rating(0)
+                }).collect::<Vec<_>>()
+            }).collect::<Vec<_>>()
+        });
+
+        let mut ratings = vec![vec![vec![500; n]; n]; SHIELD_SCENARIOS.len()];
+
+        for (i, row) in upper.iter().enumerate() {
+            for (scenario_i, v) in row.iter().enumerate() {
+                for (k, rating) in v.iter().enumerate() {
+                    let j = i + 1 + k;
+                    ratings[scenario_i][i][j] = *rating;
+                    ratings[scenario_i][j][i] = 1000 - *rating;
+                }
+            }
+        }
+
+        let mut ranking = BattleRanking { pokemons, cup, kind, ratings, scores: vec![] };
+
+        ranking.scores = (0..n).map(|i| {
+            let sum = (0..SHIELD_SCENARIOS.len()).map(|scenario_i| ranking.scenario_score(scenario_i, i)).sum::<f64>();
+            sum / SHIELD_SCENARIOS.len() as f64
+        }).collect();
+
+        ranking
+    }
+
+    /// iのjに対するバトルレーティング
+    pub fn rating(&self, scenario_i: usize, i: usize, j: usize) -> i32 {
+        self.ratings[scenario_i][i][j]
+    }
+
+    /// シナリオごとのiの平均レーティング。自分自身との対戦は除く。
+    pub fn scenario_score(&self, scenario_i: usize, i: usize) -> f64 {
+        let n = self.pokemons.len();
+
+        if n <= 1 {
+            return 500.0;
+        }
+
+        let sum = (0..n).filter(|j| *j != i).map(|j| self.rating(scenario_i, i, j)).sum::<i32>();
+        sum as f64 / (n - 1) as f64
+    }
+
+    /// 総合スコアの高い順に並べたインデックス
+    pub fn ranking(&self) -> Vec<usize> {
+        let mut v = (0..self.pokemons.len()).collect::<Vec<_>>();
+        v.sort_by(|a, b| self.scores[*b].total_cmp(&self.scores[*a]));
+
+        v
+    }
+
+    /// ポケモン名からインデックスを探す
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.pokemons.iter().position(|p| p.name() == name)
+    }
+
+    /// 総合ランキングをCSV形式で書き出す
+    pub fn save_csv<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let scenarios = SHIELD_SCENARIOS.iter().map(|s| format!("{}-{}", s[0], s[1])).collect::<Vec<_>>();
+        writeln!(writer, "rank,no,name,score,{},fast_move,charge_move1,charge_move2", scenarios.join(","))?;
+
+        for (rank, i) in self.ranking().into_iter().enumerate() {
+            let p = &self.pokemons[i];
+            let scores = (0..SHIELD_SCENARIOS.len()).map(|s| format!("{:.1}", self.scenario_score(s, i))).collect::<Vec<_>>();
+            let cm2 = p.charge_move2().map(|mv| mv.name()).unwrap_or("");
+
+            writeln!(writer, "{},{},{},{:.1},{},{},{},{}", rank + 1, p.no(), p.name(), self.scores[i], scores.join(","),
+                     p.fast_move().name(), p.charge_move1().name(), cm2)?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// シナリオのバトルレーティングの表をCSV形式で書き出す。行が自分、列が相手。
+    pub fn save_matrix_csv<W: Write>(&self, writer: &mut W, scenario_i: usize) -> Result<()> {
+        let names = self.pokemons.iter().map(|p| p.name()).collect::<Vec<_>>();
+        writeln!(writer, ",{}", names.join(","))?;
+
+        for (i, p) in self.pokemons.iter().enumerate() {
+            let v = self.ratings[scenario_i][i].iter().map(|r| r.to_string()).collect::<Vec<_>>();
+            writeln!(writer, "{},{}", p.name(), v.join(","))?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_battle_ranking() {
+    let pokemons = ["ココロモリ", "ブラッキー", "ナマズン"].iter()
+        .map(|name| league_pokemon(crate::pokepedia::pokepedia_by_name(name).unwrap(), League::GREAT).unwrap())
+        .collect::<Vec<_>>();
+
+    let ranking = BattleRanking::new(pokemons, Cup::open(League::GREAT), StrategyKind::Default);
+
+    for s in 0..SHIELD_SCENARIOS.len() {
+        for i in 0..3 {
+            for j in 0..3 {
+                if i != j {
+                    assert_eq!(ranking.rating(s, i, j) + ranking.rating(s, j, i), 1000);
+                    assert!((0..=1000).contains(&ranking.rating(s, i, j)));
+                }
+            }
+        }
+    }
+
+    let v = ranking.ranking();
+    assert_eq!(v.len(), 3);
+    assert!(ranking.scores[v[0]] >= ranking.scores[v[2]]);
+    assert_eq!(ranking.find("ナマズン"), Some(2));
+
+    let mut buf = Vec::new();
+    ranking.save_csv(&mut buf).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap().lines().count(), 4);
+}
+
+#[test]
+fn test_role_scenarios() {
+    let koko = league_pokemon(crate::pokepedia::pokepedia_by_name("ココロモリ").unwrap(), League::GREAT).unwrap();
+    let scenario = |name| ROLE_SCENARIOS.iter().find(|s| s.name == name).unwrap();
+
+    // switchは相手がエネルギーをためてノーマルアタックを始めた状態から始まり、どちらもダメージは受けていない
+    let battle = scenario("switch").battle(&koko, &koko, StrategyKind::Default, 0);
+    let state = battle.state();
+    assert_eq!(state.player0.poke().energy, 0);
+    assert!(state.player1.poke().energy > 0);
+    assert!(state.player0.switch_ms > 0);
+    assert!(!state.player0.in_fast_move);
+    assert!(state.player1.in_fast_move);
+    assert_eq!(state.player0.poke().hp(), koko.hp());
+    assert_eq!(state.player1.poke().hp(), koko.hp());
+    assert_eq!(state.turn, 0);
+
+    // attackerはシールド0対1
+    let battle = scenario("attacker").battle(&koko, &koko, StrategyKind::Default, 0);
+    assert_eq!(battle.state().player0.num_shields, 0);
+    assert_eq!(battle.state().player1.num_shields, 1);
+
+    // 不利な条件から始めると、平均すると負け越す
+    let pokemons = ["ココロモリ", "ブラッキー", "ナマズン"].iter()
+        .map(|name| league_pokemon(crate::pokepedia::pokepedia_by_name(name).unwrap(), League::GREAT).unwrap())
+        .collect::<Vec<_>>();
+
+    let scores = pokemons.iter().map(|p| role_scores(p, &pokemons, StrategyKind::Default)).collect::<Vec<_>>();
+
+    for name in ["switch", "attacker"] {
+        let s = ROLE_SCENARIOS.iter().position(|s| s.name == name).unwrap();
+        let avg = scores.iter().map(|v| v[s]).sum::<f64>() / 3.0;
+        assert!(avg < 500.0, "{}: {}", name, avg);
+    }
+
+    // 手持ちのポケモンの役割のスコアは、総当たりのランキングを作らずに相手のポケモンだけで求める
+    let opponents = cup_pokemons(&Cup { allowed: vec!["0528", "0197"], ..Cup::open(League::GREAT) });
+    let scores = role_scores(&koko, &opponents, StrategyKind::Default);
+    assert_eq!(opponents.len(), 2);
+    assert_eq!(scores.len(), ROLE_SCENARIOS.len());
+    assert!(scores.iter().all(|s| (0.0..=1000.0).contains(s)));
+}
+
+#[test]
+fn test_scp_ranking_cup() {
+    // allowedのポケモンだけ使えるカップ
+    let cup = Cup { allowed: vec!["0528", "0340"], ..Cup::open(League::GREAT) };
+    let v = scp_ranking(&cup);
+
+    assert_eq!(v.iter().map(|p| p.name()).collect::<Vec<_>>(), ["ナマズン", "ココロモリ"]);
+    assert!(v.iter().all(|p| cup.ineligible_reason(p).is_none()));
+}
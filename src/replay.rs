@@ -0,0 +1,132 @@
+//! バトルの記録(リプレイ)ファイル
+//! 開始時の状態、乱数の種、戦略、行動の列をTOMLで保存する。
+//! 読み込むときは同じ条件でバトルをやり直して状態の列を作り、保存したログと一致するかを確かめる。
+//! ログが一致しなければ、エンジンの変更で結果が変わったということになる。
+
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use serde::{Serialize, Deserialize};
+
+use crate::battle::{Battle, Action};
+use crate::scenario::ScenarioToml;
+use crate::strategy::strategy_by_name;
+use crate::utils::jp_width;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReplayToml {
+    seed: u64,
+    strategies: [String; 2],  // 戦略の名前
+    actions: Vec<[String; 2]>,  // 各ターンの行動
+    logs: Vec<String>,  // 各ターンのログ。確認用
+    start: ScenarioToml,  // 開始時の状態
+}
+
+/// ログを書き出すときの名前の幅
+fn log_width(battle: &Battle) -> usize {
+    let start = &battle.states[0];
+
+    start.player0.team.iter().chain(start.player1.team.iter())
+        .map(|p| jp_width(p.name())).max().unwrap_or(0)
+}
+
+/// バトルを記録する
+pub fn save_replay<W: Write>(writer: &mut W, battle: &Battle) -> Result<()> {
+    let width = log_width(battle);
+
+    let data = ReplayToml {
+        seed: battle.seed,
+        strategies: [0, 1].map(|i| battle.strategies[i].name().to_string()),
+        actions: battle.actions.iter().map(|actions| actions.map(|a| a.to_string())).collect(),
+        logs: battle.logs.iter().map(|log| log.format(width)).collect(),
+        start: ScenarioToml::from_state(&battle.states[0]),
+    };
+
+    let contents = toml::to_string_pretty(&data)?;
+
+    writer.write_all(contents.as_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// 記録を読み込んで、同じバトルを再現する
+pub fn load_replay<R: Read>(reader: &mut R) -> Result<Battle> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+
+    let data: ReplayToml = toml::from_str(&contents)?;
+
+    let mut battle = Battle::from_state(data.start.to_state()?);
+    battle.set_seed(data.seed);
+
+    for (i, name) in data.strategies.iter().enumerate() {
+        match strategy_by_name(name) {
+            Some(kind) => battle.set_strategy(i, kind.create()),
+            None => bail!("存在しない戦略: {}", name),
+        }
+    }
+
+    for (turn, actions) in data.actions.iter().enumerate() {
+        let actions = [actions[0].parse::<Action>(), actions[1].parse::<Action>()];
+
+        match actions {
+            [Ok(a0), Ok(a1)] => {
+                if battle.winner().is_some() {
+                    bail!("{}ターン目より前にバトルが終わっている", turn + 1);
+                }
+
+                // 戦略が行動を決めるときにも乱数を使うので、同じように呼び出してから記録した行動をとる
+                battle.next_actions();
+                battle.do_action([a0, a1]);
+            },
+            [Err(err), _] | [_, Err(err)] => bail!(err),
+        }
+    }
+
+    let width = log_width(&battle);
+
+    for (log, saved) in battle.logs.iter().zip(&data.logs) {
+        if log.format(width) != *saved {
+            bail!("{}ターン目のログが一致しない\n記録: {}\n再現: {}", log.turn, saved, log.format(width));
+        }
+    }
+
+    if battle.logs.len() != data.logs.len() {
+        bail!("ログの数が一致しない。記録: {}, 再現: {}", data.logs.len(), battle.logs.len());
+    }
+
+    Ok(battle)
+}
+
+#[test]
+fn test_replay() {
+    use std::io::Cursor;
+    use crate::battle::PlayerOptions;
+    use crate::pokemon::Pokemon;
+    use crate::strategy::StrategyKind;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let mut battle = Battle::new(String::from("p0"), vec![p0, p2.clone()], String::from("p1"), vec![p1, p2]);
+    battle.set_strategy(0, StrategyKind::Random.create());
+    battle.set_strategy(1, StrategyKind::Smart.create());
+    battle.set_player_options(1, &PlayerOptions { num_shields: Some(1), ..PlayerOptions::default() });
+    battle.set_seed(5);
+    battle.start();
+
+    let mut buf = vec![];
+    save_replay(&mut buf, &battle).unwrap();
+
+    let loaded = load_replay(&mut Cursor::new(&buf)).unwrap();
+    assert_eq!(loaded.states, battle.states);
+    assert_eq!(loaded.actions, battle.actions);
+    assert_eq!(loaded.seed, battle.seed);
+    assert_eq!(loaded.strategies[1].name(), "smart");
+
+    // ログが書き換えられていたらエラー
+    let contents = String::from_utf8(buf).unwrap().replacen("ココロモリ HP", "ココロモリ HQ", 1);
+    assert!(load_replay(&mut Cursor::new(contents)).is_err());
+}
@@ -0,0 +1,303 @@
+//! バトルの途中の局面をTOMLで書いたシナリオ
+//! 両プレイヤーのポケモンのHP、エネルギー、ランク補正や、シールドの数などを指定して、
+//! その局面からの最善の進行を探索するのに使う。
+//!
+//! ```toml
+//! [rules]
+//! fast_move_timing = "real"
+//! cm_bonus = 0.9
+//!
+//! [player0]
+//! name = "自分"
+//! num_shields = 0
+//! cur_poke = 0
+//!
+//! [[player0.team]]
+//! name = "ココロモリ"
+//! cp = 1489
+//! ivs = { attack = 10, defense = 9, stamina = 12 }
+//! fast_move = "エアスラッシュ"
+//! charge_move1 = "サイコファング"
+//! hp = 43
+//! energy = 55
+//! ```
+//!
+//! 以前の形式のトップレベルのfast_move_timingも読み込める。知らない項目があればエラーにする。
+
+use std::io::Read;
+
+use anyhow::{Result, bail, anyhow};
+use serde::{Serialize, Deserialize};
+
+use crate::battle::{State, Player, BattleRules, FastMoveTiming};
+use crate::pokemon::{Pokemon, IVs};
+
+/// シナリオのTOMLの内容。バトルの記録(replay)にも開始時の状態として埋め込む
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioToml {
+    turn: Option<i32>,  // 経過ターン数。省略すると0
+    elapsed_ms: Option<i32>,  // 経過時間(ミリ秒)。省略するとターン数から計算する
+    rules: Option<BattleRules>,  // バトルのルール。省略した項目は既定値
+
+    // 以前の形式のノーマルアタックのダメージが入るタイミング。書き出すときはrulesに入れる
+    #[serde(default, skip_serializing)]
+    fast_move_timing: Option<FastMoveTiming>,
+
+    player0: PlayerToml,
+    player1: PlayerToml,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlayerToml {
+    name: Option<String>,
+    team: Vec<ScenarioPokemonToml>,
+    cur_poke: Option<usize>,  // 場に出ているポケモンのインデックス。省略すると0
+    num_shields: Option<i32>,  // 省略するとルールの数
+    switch_ms: Option<i32>,  // 交代できるようになるまでのミリ秒。省略すると0
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioPokemonToml {
+    name: String,
+    cp: i32,
+    lv: Option<f32>,  // ポケモンレベル。省略するとCPと個体値から計算する
+    ivs: IVs,
+
+    fast_move: String,
+    charge_move1: String,
+    charge_move2: Option<String>,
+
+    hp: Option<i32>,  // 残りHP。省略すると最大HP
+    energy: Option<i32>,  // 省略すると0
+    buff: Option<(i32, i32)>,  // (攻撃ランク, 防御ランク)。省略すると(0, 0)
+}
+
+/// シナリオを読み込んで、その局面の状態を作る
+pub fn load_scenario<R: Read>(reader: &mut R) -> Result<State> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+
+    let data: ScenarioToml = toml::from_str(&contents)?;
+    let state = data.to_state()?;
+
+    for p in [&state.player0, &state.player1] {
+        if p.poke().is_faint() {
+            bail!("{}の場に出ているポケモンが瀕死", p.name);
+        }
+    }
+
+    Ok(state)
+}
+
+impl ScenarioToml {
+    pub fn from_state(state: &State) -> Self {
+        let player = |p: &Player| PlayerToml {
+            name: Some(p.name.clone()),
+            team: p.team.iter().map(|poke| ScenarioPokemonToml {
+                name: poke.name().to_string(),
+                cp: poke.cp(),
+                lv: Some(poke.lv()),
+                ivs: poke.ivs(),
+                fast_move: poke.fast_move().name().to_string(),
+                charge_move1: poke.charge_move1().name().to_string(),
+                charge_move2: poke.charge_move2().map(|mv| mv.name().to_string()),
+                hp: Some(poke.hp()),
+                energy: Some(poke.energy),
+                buff: Some(poke.buff),
+            }).collect(),
+            cur_poke: Some(p.cur_poke),
+            num_shields: Some(p.num_shields),
+            switch_ms: Some(p.switch_ms),
+        };
+
+        ScenarioToml {
+            turn: Some(state.turn),
+            elapsed_ms: Some(state.elapsed_ms),
+            rules: Some(state.rules),
+            fast_move_timing: None,
+            player0: player(&state.player0),
+            player1: player(&state.player1),
+        }
+    }
+
+    pub fn to_state(&self) -> Result<State> {
+        let mut rules = self.rules.unwrap_or_default();
+
+        if let Some(timing) = self.fast_move_timing {
+            rules.fast_move_timing = timing;
+        }
+        let player0 = build_player(&self.player0, "player0", &rules)?;
+        let player1 = build_player(&self.player1, "player1", &rules)?;
+
+        let mut state = State::new(player0, player1);
+        state.rules = rules;
+
+        if let Some(turn) = self.turn {
+            if turn < 0 {
+                bail!("turnが負の値: {}", turn);
+            }
+
+            state.turn = turn;
+            state.elapsed_ms = self.elapsed_ms.unwrap_or(turn * crate::battle::MS_PER_TURN);
+        }
+
+        Ok(state)
+    }
+}
+
+fn build_player(data: &PlayerToml, default_name: &str, rules: &BattleRules) -> Result<Player> {
+    if data.team.is_empty() {
+        bail!("{}のポケモンがいない", default_name);
+    }
+
+    let mut team = vec![];
+
+    for d in &data.team {
+        let ivs = (d.ivs.attack, d.ivs.defense, d.ivs.stamina);
+        let poke = Pokemon::new(&d.name, d.lv, ivs, &d.fast_move, &d.charge_move1, d.charge_move2.clone(), d.cp)
+            .map_err(|err| anyhow!("{}: {}", d.name, err))?;
+
+        team.push(poke);
+    }
+
+    let name = data.name.clone().unwrap_or_else(|| default_name.to_string());
+    let mut player = Player::new(name, team);
+    player.num_shields = rules.num_shields;
+
+    for (poke, d) in player.team.iter_mut().zip(&data.team) {
+        if let Some(hp) = d.hp {
+            if !(0..=poke.base_hp()).contains(&hp) {
+                bail!("{}のHPは0から{}の値: {}", d.name, poke.base_hp(), hp);
+            }
+
+            poke.hp = hp;
+        }
+
+        if let Some(energy) = d.energy {
+            if !(0..=100).contains(&energy) {
+                bail!("{}のエネルギーは0から100の値: {}", d.name, energy);
+            }
+
+            poke.energy = energy;
+        }
+
+        if let Some(buff) = d.buff {
+            if !(-4..=4).contains(&buff.0) || !(-4..=4).contains(&buff.1) {
+                bail!("{}のランク補正は-4から4の値: {:?}", d.name, buff);
+            }
+
+            poke.buff = buff;
+        }
+    }
+
+    if let Some(cur_poke) = data.cur_poke {
+        if cur_poke >= player.team.len() {
+            bail!("{}のcur_pokeが範囲外: {}", player.name, cur_poke);
+        }
+
+        player.cur_poke = cur_poke;
+    }
+
+    if let Some(num_shields) = data.num_shields {
+        if num_shields < 0 {
+            bail!("{}のシールドの数が負の値: {}", player.name, num_shields);
+        }
+
+        player.num_shields = num_shields;
+    }
+
+    if let Some(switch_ms) = data.switch_ms {
+        player.switch_ms = std::cmp::max(switch_ms, 0);
+    }
+
+    Ok(player)
+}
+
+#[test]
+fn test_load_scenario() {
+    use std::io::Cursor;
+    use crate::battle::Phase;
+    use crate::solver::Solver;
+
+    let scenario = r#"
+[rules]
+fast_move_timing = "real"
+cm_bonus = 0.9
+
+[player0]
+name = "自分"
+num_shields = 0
+
+[[player0.team]]
+name = "ココロモリ"
+cp = 1489
+ivs = { attack = 10, defense = 9, stamina = 12 }
+fast_move = "エアスラッシュ"
+charge_move1 = "サイコファング"
+hp = 43
+energy = 55
+
+[player1]
+num_shields = 1
+switch_ms = 30000
+cur_poke = 1
+
+[[player1.team]]
+name = "ブラッキー"
+cp = 1498
+ivs = { attack = 2, defense = 14, stamina = 0 }
+fast_move = "バークアウト"
+charge_move1 = "あくのはどう"
+hp = 0
+
+[[player1.team]]
+name = "ナマズン"
+cp = 1474
+ivs = { attack = 8, defense = 15, stamina = 14 }
+fast_move = "みずでっぽう"
+charge_move1 = "どろばくだん"
+hp = 20
+buff = [1, -1]
+"#;
+
+    let state = load_scenario(&mut Cursor::new(scenario)).unwrap();
+
+    assert_eq!(state.rules.fast_move_timing, FastMoveTiming::Real);
+    assert_eq!(state.rules.cm_bonus, 0.9);
+    assert_eq!(state.rules.limit_turn, BattleRules::default().limit_turn);
+    assert_eq!(state.player0.name, "自分");
+    assert_eq!(state.player0.num_shields, 0);
+    assert_eq!(state.player0.poke().hp(), 43);
+    assert_eq!(state.player0.poke().energy, 55);
+
+    assert_eq!(state.player1.name, "player1");
+    assert_eq!(state.player1.poke_name(), "ナマズン");
+    assert_eq!(state.player1.poke().buff, (1, -1));
+    assert_eq!(state.player1.switch_ms, 30000);
+    assert_eq!(state.player1.num_remains(), 1);
+    assert_eq!(state.phase, Phase::Neutral);
+
+    let result = Solver::new(30).solve(&state);
+    assert!(!result.line.is_empty());
+
+    // 書き出して読み込むと同じ状態になる
+    let saved = toml::to_string(&ScenarioToml::from_state(&state)).unwrap();
+    assert_eq!(load_scenario(&mut Cursor::new(saved)).unwrap(), state);
+
+    // 場に出ているポケモンが瀕死ならエラー
+    let bad = scenario.replace("cur_poke = 1", "cur_poke = 0");
+    assert!(load_scenario(&mut Cursor::new(bad)).is_err());
+
+    // 以前の形式のトップレベルのfast_move_timingも読み込める
+    let old = format!("fast_move_timing = \"real\"\n{}", scenario.replace("fast_move_timing = \"real\"\n", ""));
+    assert_eq!(load_scenario(&mut Cursor::new(old)).unwrap().rules.fast_move_timing, FastMoveTiming::Real);
+
+    // 知らない項目はエラー
+    let bad = scenario.replace("cm_bonus = 0.9", "cm_bonus = 0.9\nfast_move_timeing = \"real\"");
+    assert!(load_scenario(&mut Cursor::new(bad)).is_err());
+    let bad = scenario.replace("num_shields = 1", "num_shield = 1");
+    assert!(load_scenario(&mut Cursor::new(bad)).is_err());
+}
@@ -0,0 +1,318 @@
+//! ゲーム木探索によるバトルの解析
+//! 両プレイヤーの選択(ノーマルアタックかスペシャルアタックか、どのわざか、シールドを使うか、誰を出すか)を
+//! ミニマックスで、確率で決まること(ステータス変化、先攻)を期待値で評価する(expectimax)。
+//!
+//! 評価値はプレイヤー0から見たバトルレーティング(0.0～1000.0)。プレイヤー0が最大化し、プレイヤー1が最小化する。
+//! 実際には同時に行動を選ぶが、ここではプレイヤー1がプレイヤー0の行動を見てから選ぶものとする。
+//! そのため評価値はプレイヤー0にとって控えめな値になる。
+
+use std::collections::HashMap;
+
+use crate::battle::{State, Action, Phase, Decider, Log};
+use crate::moves::ChargeMove;
+
+/// 探索の途中で現れる分岐
+#[derive(Debug, Clone)]
+enum Branch {
+    Shield(usize),  // シールドを使うか([data] プレイヤー)。0: 使う, 1: 使わない
+    Switch(usize, Vec<usize>),  // 瀕死後に出すポケモン([data] プレイヤー, 候補のインデックス)
+    Chance(f64),  // 確率で起きること([data] 起きる確率)。0: 起きる, 1: 起きない
+}
+
+/// 決められた選択の列にしたがって判断するDecider。
+/// 列が尽きたら最初の分岐を記録して、あとは既定の選択をする。
+struct ScriptDecider<'a> {
+    script: &'a [usize],
+    pos: usize,
+    branch: Option<Branch>,
+}
+
+impl ScriptDecider<'_> {
+    fn next(&mut self, branch: Branch) -> usize {
+        let i = self.pos;
+        self.pos += 1;
+
+        if i < self.script.len() {
+            self.script[i]
+        } else {
+            if self.branch.is_none() {
+                self.branch = Some(branch);
+            }
+
+            0
+        }
+    }
+}
+
+impl Decider for ScriptDecider<'_> {
+    fn shield(&mut self, _state: &State, player_i: usize, _mv: &'static ChargeMove) -> bool {
+        self.next(Branch::Shield(player_i)) == 0
+    }
+
+    fn switch(&mut self, state: &State, player_i: usize) -> usize {
+        let p = state.player(player_i);
+        let candidates = (0..p.team.len()).filter(|i| *i != p.cur_poke && !p.team[*i].is_faint()).collect::<Vec<_>>();
+
+        match candidates.len() {
+            0 => 0,
+            1 => candidates[0],
+            _ => {
+                let i = self.next(Branch::Switch(player_i, candidates.clone()));
+                candidates[i]
+            },
+        }
+    }
+
+    fn chance(&mut self, prob: f64) -> bool {
+        if prob <= 0.0 {
+            false
+        } else if prob >= 1.0 {
+            true
+        } else {
+            self.next(Branch::Chance(prob)) == 0
+        }
+    }
+}
+
+/// 探索で求めた1ターン分の最善の進行
+#[derive(Debug, Clone)]
+pub struct LineStep {
+    pub actions: [Action; 2],
+    pub log: Log,
+    pub state: State,  // このターンの後の状態
+}
+
+/// 探索の結果
+#[derive(Debug, Clone)]
+pub struct SolveResult {
+    pub value: f64,  // プレイヤー0から見たバトルレーティングの期待値
+    pub line: Vec<LineStep>,  // 最善の進行。確率で分かれるところは起きやすい方をたどる
+    pub num_nodes: usize,  // 評価した状態の数
+}
+
+/// ゲーム木探索のソルバー
+pub struct Solver {
+    pub max_turns: i32,  // 探索するターン数。これより先は残りHPで評価する
+    pub allow_switch: bool,  // 自分から交代する行動も探索するか
+    memo: HashMap<Vec<i32>, f64>,
+    end_turn: i32,
+}
+
+impl Solver {
+    pub fn new(max_turns: i32) -> Self {
+        Self { max_turns, allow_switch: false, memo: HashMap::new(), end_turn: 0 }
+    }
+
+    /// stateから探索して、評価値と最善の進行を返す
+    pub fn solve(&mut self, state: &State) -> SolveResult {
+        self.memo.clear();
+        self.end_turn = state.turn + self.max_turns;
+
+        let value = self.value(state);
+
+        let mut line = vec![];
+        let mut state = state.clone();
+
+        while !self.is_leaf(&state) {
+            let (actions, _) = self.best_actions(&state);
+            let (_, script) = self.step_value(&state, actions, &mut vec![]);
+
+            let mut decider = ScriptDecider { script: &script, pos: 0, branch: None };
+            let (next, events) = state.step(actions, &mut decider);
+
+            line.push(LineStep { actions, log: Log::new(&next, events), state: next.clone() });
+            state = next;
+        }
+
+        SolveResult { value, line, num_nodes: self.memo.len() }
+    }
+
+    fn is_leaf(&self, state: &State) -> bool {
+        state.phase != Phase::Neutral || state.turn >= self.end_turn
+    }
+
+    /// プレイヤー0から見たstateの評価値
+    fn value(&mut self, state: &State) -> f64 {
+        if self.is_leaf(state) {
+            return state.rating(0);
+        }
+
+        let key = state_key(state);
+
+        if let Some(v) = self.memo.get(&key) {
+            return *v;
+        }
+
+        let (_, v) = self.best_actions(state);
+        self.memo.insert(key, v);
+
+        v
+    }
+
+    /// プレイヤー0が最大化、プレイヤー1が最小化する行動の組と評価値
+    fn best_actions(&mut self, state: &State) -> ([Action; 2], f64) {
+        let actions0 = self.candidate_actions(state, 0);
+        let actions1 = self.candidate_actions(state, 1);
+
+        let mut best = ([Action::FastMove; 2], f64::MIN);
+
+        for a0 in &actions0 {
+            let mut worst = ([*a0, Action::FastMove], f64::MAX);
+
+            for a1 in &actions1 {
+                let (v, _) = self.step_value(state, [*a0, *a1], &mut vec![]);
+
+                if v < worst.1 {
+                    worst = ([*a0, *a1], v);
+                }
+            }
+
+            if worst.1 > best.1 {
+                best = worst;
+            }
+        }
+
+        best
+    }
+
+    /// player_iが選べる行動。硬直中や瀕死のときはノーマルアタックだけ
+    fn candidate_actions(&self, state: &State, player_i: usize) -> Vec<Action> {
+        let p = state.player(player_i);
+        let poke = p.poke();
+        let mut v = vec![Action::FastMove];
+
+        if p.in_fast_move || poke.is_faint() {
+            return v;
+        }
+
+        for i in 0..2 {
+            if poke.can_charge_move(i) {
+                v.push(Action::ChargeMove(i as u8));
+            }
+        }
+
+        if self.allow_switch && p.switch_ms == 0 {
+            for i in 0..p.team.len() {
+                if i != p.cur_poke && !p.team[i].is_faint() {
+                    v.push(Action::SwitchPokemon(i as u8));
+                }
+            }
+        }
+
+        v
+    }
+
+    /// actionsで1ターン進めたときの評価値。ターンの途中の分岐はscriptに選択を積みながら列挙する。
+    /// 最善の選択の列も返す。
+    fn step_value(&mut self, state: &State, actions: [Action; 2], script: &mut Vec<usize>) -> (f64, Vec<usize>) {
+        let mut decider = ScriptDecider { script, pos: 0, branch: None };
+        let (next, _) = state.step(actions, &mut decider);
+
+        let branch = match decider.branch {
+            None => return (self.value(&next), script.clone()),
+            Some(branch) => branch,
+        };
+
+        let num_options = match &branch {
+            Branch::Shield(_) | Branch::Chance(_) => 2,
+            Branch::Switch(_, candidates) => candidates.len(),
+        };
+
+        let values = (0..num_options).map(|i| {
+            script.push(i);
+            let r = self.step_value(state, actions, script);
+            script.pop();
+            r
+        }).collect::<Vec<_>>();
+
+        match branch {
+            Branch::Shield(player_i) | Branch::Switch(player_i, _) => {
+                let cmp = |a: &&(f64, Vec<usize>), b: &&(f64, Vec<usize>)| a.0.total_cmp(&b.0);
+                let best = if player_i == 0 { values.iter().max_by(cmp) } else { values.iter().min_by(cmp) };
+                best.unwrap().clone()
+            },
+
+            Branch::Chance(prob) => {
+                let value = prob * values[0].0 + (1.0 - prob) * values[1].0;
+                let likely = if prob >= 0.5 { &values[0] } else { &values[1] };
+                (value, likely.1.clone())
+            },
+        }
+    }
+}
+
+/// メモ化に使う状態のキー。探索に関係するものだけを並べる
+fn state_key(state: &State) -> Vec<i32> {
+    let mut key = vec![state.turn];
+
+    for p in [&state.player0, &state.player1] {
+        key.extend([p.cur_poke as i32, p.num_shields, p.switch_ms, p.in_fast_move as i32, p.dur_turns]);
+
+        for poke in &p.team {
+            key.extend([poke.hp, poke.energy, poke.buff.0, poke.buff.1]);
+        }
+    }
+
+    key
+}
+
+#[test]
+fn test_solver() {
+    use crate::battle::{Battle, BattleRules};
+    use crate::pokemon::Pokemon;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let mut battle = Battle::new(String::from("p0"), vec![p0], String::from("p1"), vec![p1]);
+    battle.set_rules(BattleRules { num_shields: 1, ..BattleRules::default() });
+
+    let mut solver = Solver::new(40);
+    let result = solver.solve(battle.state());
+
+    assert!((0.0..=1000.0).contains(&result.value));
+    assert!(!result.line.is_empty());
+    assert!(result.num_nodes > 0);
+
+    // 最善の進行をたどると、探索の終わりか勝敗がつくところまで進む
+    let last = &result.line.last().unwrap().state;
+    assert!(last.phase != Phase::Neutral || last.turn >= battle.state().turn + 40);
+
+    // 同じ状態なら同じ結果
+    let result2 = Solver::new(40).solve(battle.state());
+    assert_eq!(result.value, result2.value);
+}
+
+#[test]
+fn test_solver_finishing_charge_move() {
+    use crate::battle::{Battle, BattleRules, PlayerOptions};
+    use crate::pokemon::Pokemon;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    // 相手は残りHPが少なく、シールドもない。自分はスペシャルアタックを打てる
+    let mut battle = Battle::new(String::from("p0"), vec![p0], String::from("p1"), vec![p1]);
+    battle.set_rules(BattleRules { num_shields: 0, ..BattleRules::default() });
+    battle.set_player_options(0, &PlayerOptions { energy: 50, ..PlayerOptions::default() });
+    battle.set_player_options(1, &PlayerOptions { hp_percent: 10, ..PlayerOptions::default() });
+
+    let state = battle.state();
+    let (poke0, poke1) = (state.player0.poke(), state.player1.poke());
+
+    // ノーマルアタックでは倒せないが、スペシャルアタックなら倒せる
+    assert!(poke0.calc_fast_move_damage(poke1, &state.rules) < poke1.hp());
+    assert!(poke0.calc_charge_move_damage(poke0.charge_move1(), poke1, &state.rules) >= poke1.hp());
+
+    let result = Solver::new(20).solve(state);
+
+    // すぐにスペシャルアタックで倒すのが最善。相手は同じターンにノーマルアタックを1回だけ当てられる
+    let first = &result.line[0];
+    assert_eq!(first.actions[0], Action::ChargeMove(0));
+    assert_eq!(first.state.phase, Phase::GameOver(0));
+    assert_eq!(result.line.len(), 1);
+
+    let damage = poke1.calc_fast_move_damage(poke0, &state.rules);
+    let expected = 500.0 + 500.0 * ((poke0.hp() - damage) as f64 / poke0.base_hp() as f64);
+    assert_eq!(result.value, expected);
+}
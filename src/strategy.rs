@@ -0,0 +1,639 @@
+//! バトルの戦略(AI)
+//! 戦略はプレイヤーごとに選ぶことができる。
+
+use rand::prelude::*;
+
+use crate::battle::{State, Action, BattleRng, BattlePokemon, BattleRules};
+use crate::moves::ChargeMove;
+
+/// バトルの戦略
+///
+/// player_iは自分のプレイヤー番号。state.player(player_i)が自分、state.opponent(player_i)が相手になる。
+/// &mut selfで呼ばれるので、ターンをまたいで記憶を持つことができる。
+/// 乱数を使う場合は、結果を再現できるように引数のrng(バトルの乱数生成器)を使う。
+pub trait BattleStrategy {
+    /// 戦略の名前
+    fn name(&self) -> &'static str;
+
+    /// 毎ターンの行動を決める
+    fn action(&mut self, state: &State, player_i: usize, rng: &mut BattleRng) -> Action;
+
+    /// 相手のスペシャルアタックmvに対してシールドを使うかを決める。
+    /// シールドが残っているときだけ呼ばれる。
+    fn shield(&mut self, _state: &State, _player_i: usize, _mv: &'static ChargeMove, _rng: &mut BattleRng) -> bool {
+        true
+    }
+
+    /// ポケモンが瀕死になったときに、次に出すポケモンのインデックスを決める。
+    /// 出せないポケモンを返した場合は、Player::force_switchで選ばれる。
+    fn switch(&mut self, _state: &State, _player_i: usize, _rng: &mut BattleRng) -> usize {
+        0
+    }
+}
+
+/// 組み込みの戦略の種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StrategyKind {
+    Default,  // スペシャルアタック1を打てるようになったらすぐに打つ
+    FastMoveOnly,  // ノーマルアタックしか使わない
+    Random,  // ランダムに行動する
+    Greedy,  // 打てるスペシャルアタックのうち、一番ダメージが大きいものを打つ
+    SmartShield,  // デフォルトの行動で、シールドは大きなダメージを受けるときだけ使う
+    Bait,  // 相手にシールドがある間は安いスペシャルアタックで削り、なくなったら効率の良いものを打つ
+    Smart,  // Baitの行動とShieldPolicyに加えて、相性を見てポケモンを交代する
+}
+
+pub const STRATEGY_KINDS: [StrategyKind; 7] = [
+    StrategyKind::Default, StrategyKind::FastMoveOnly, StrategyKind::Random, StrategyKind::Greedy,
+    StrategyKind::SmartShield, StrategyKind::Bait, StrategyKind::Smart,
+];
+
+impl StrategyKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StrategyKind::Default => "default",
+            StrategyKind::FastMoveOnly => "fast",
+            StrategyKind::Random => "random",
+            StrategyKind::Greedy => "greedy",
+            StrategyKind::SmartShield => "shield",
+            StrategyKind::Bait => "bait",
+            StrategyKind::Smart => "smart",
+        }
+    }
+
+    pub fn create(&self) -> Box<dyn BattleStrategy> {
+        match self {
+            StrategyKind::Default => Box::new(DefaultStrategy),
+            StrategyKind::FastMoveOnly => Box::new(FastMoveOnlyStrategy),
+            StrategyKind::Random => Box::new(RandomStrategy),
+            StrategyKind::Greedy => Box::new(GreedyStrategy),
+            StrategyKind::SmartShield => Box::new(SmartShieldStrategy::default()),
+            StrategyKind::Bait => Box::new(BaitStrategy),
+            StrategyKind::Smart => Box::new(SmartStrategy::default()),
+        }
+    }
+}
+
+/// 名前から戦略の種類を取得する。
+pub fn strategy_by_name(name: &str) -> Option<StrategyKind> {
+    STRATEGY_KINDS.into_iter().find(|k| k.name() == name)
+}
+
+#[test]
+fn test_strategy_by_name() {
+    assert_eq!(strategy_by_name("greedy"), Some(StrategyKind::Greedy));
+    assert_eq!(strategy_by_name("hoge"), None);
+
+    for kind in STRATEGY_KINDS {
+        assert_eq!(kind.create().name(), kind.name());
+    }
+}
+
+/// 今までのデフォルトの戦略。
+/// スペシャルアタック1を打てるようになったらすぐに打ち、シールドは常に使い、
+/// 瀕死になったらインデックス順に交代する。
+pub struct DefaultStrategy;
+
+impl BattleStrategy for DefaultStrategy {
+    fn name(&self) -> &'static str {
+        StrategyKind::Default.name()
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, _rng: &mut BattleRng) -> Action {
+        if state.player(player_i).poke().can_charge_move1() {
+            Action::ChargeMove(0)
+        } else {
+            Action::FastMove
+        }
+    }
+}
+
+/// ノーマルアタックしか使わない
+pub struct FastMoveOnlyStrategy;
+
+impl BattleStrategy for FastMoveOnlyStrategy {
+    fn name(&self) -> &'static str {
+        StrategyKind::FastMoveOnly.name()
+    }
+
+    fn action(&mut self, _state: &State, _player_i: usize, _rng: &mut BattleRng) -> Action {
+        Action::FastMove
+    }
+}
+
+/// 打てる行動の中からランダムに選ぶ。シールドを使うか、誰に交代するかもランダム。
+pub struct RandomStrategy;
+
+impl BattleStrategy for RandomStrategy {
+    fn name(&self) -> &'static str {
+        StrategyKind::Random.name()
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, rng: &mut BattleRng) -> Action {
+        let poke = state.player(player_i).poke();
+        let mut actions = vec![Action::FastMove];
+
+        for i in 0..2 {
+            if poke.can_charge_move(i) {
+                actions.push(Action::ChargeMove(i as u8));
+            }
+        }
+
+        *actions.choose(rng).unwrap()
+    }
+
+    fn shield(&mut self, _state: &State, _player_i: usize, _mv: &'static ChargeMove, rng: &mut BattleRng) -> bool {
+        rng.gen()
+    }
+
+    fn switch(&mut self, state: &State, player_i: usize, rng: &mut BattleRng) -> usize {
+        let p = state.player(player_i);
+        let candidates = (0..p.team.len()).filter(|i| *i != p.cur_poke && !p.team[*i].is_faint()).collect::<Vec<_>>();
+
+        candidates.choose(rng).copied().unwrap_or(0)
+    }
+}
+
+/// 打てるスペシャルアタックのうち、相手に一番ダメージを与えるものを打つ
+pub struct GreedyStrategy;
+
+impl BattleStrategy for GreedyStrategy {
+    fn name(&self) -> &'static str {
+        StrategyKind::Greedy.name()
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, _rng: &mut BattleRng) -> Action {
+        let poke = state.player(player_i).poke();
+        let opponent = state.opponent(player_i).poke();
+
+        let best = (0..2)
+            .filter(|i| poke.can_charge_move(*i))
+            .max_by_key(|i| poke.calc_charge_move_damage(poke.charge_move(*i).unwrap(), opponent, &state.rules));
+
+        match best {
+            Some(i) => Action::ChargeMove(i as u8),
+            None => Action::FastMove,
+        }
+    }
+}
+
+#[test]
+fn test_fast_move_only_strategy() {
+    use crate::battle::{Battle, Event};
+    use crate::pokemon::Pokemon;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+
+    let mut battle = Battle::new(String::from("p0"), vec![p0], String::from("p1"), vec![p1]);
+    battle.set_strategy(0, StrategyKind::FastMoveOnly.create());
+    battle.set_strategy(1, StrategyKind::Greedy.create());
+    battle.start();
+
+    assert!(battle.logs.iter().all(|log| !log.events0.iter().any(|e| matches!(e, Event::ChargeMove(..)))));
+    assert!(battle.logs.iter().any(|log| log.events1.iter().any(|e| matches!(e, Event::ChargeMove(..)))));
+}
+
+/// シールドを使うかの判断。
+/// 受けるダメージを見積もって、瀕死になるか、最大HPのdamage_ratio以上を削られるときだけシールドを使う。
+#[derive(Debug, Clone, Copy)]
+pub struct ShieldPolicy {
+    pub damage_ratio: f64,  // シールドを使う被ダメージの割合(最大HPに対する割合)
+}
+
+impl Default for ShieldPolicy {
+    fn default() -> Self {
+        Self { damage_ratio: 0.5 }
+    }
+}
+
+impl ShieldPolicy {
+    /// 相手のスペシャルアタックmvに対してシールドを使うべきか
+    pub fn should_shield(&self, state: &State, player_i: usize, mv: &ChargeMove) -> bool {
+        let p = state.player(player_i);
+        let o = state.opponent(player_i);
+
+        if p.num_shields <= 0 {
+            return false;
+        }
+
+        let poke = p.poke();
+        let damage = o.poke().calc_charge_move_damage(mv, poke, &state.rules);
+
+        // 瀕死になるなら必ず使う
+        if damage >= poke.hp() {
+            return true;
+        }
+
+        let mut damage_ratio = self.damage_ratio;
+
+        // 残りのポケモンよりシールドが多ければ、余らせても仕方ないので使いやすくする
+        if p.num_shields >= p.num_remains() {
+            damage_ratio *= 0.5;
+        }
+
+        // 相手の方がシールドが多ければ、後のために温存する
+        if o.num_shields > p.num_shields {
+            damage_ratio *= 1.25;
+        }
+
+        damage as f64 >= poke.base_hp() as f64 * damage_ratio
+    }
+}
+
+/// 行動はDefaultStrategyと同じで、シールドはShieldPolicyで判断する
+#[derive(Default)]
+pub struct SmartShieldStrategy {
+    pub policy: ShieldPolicy,
+}
+
+impl BattleStrategy for SmartShieldStrategy {
+    fn name(&self) -> &'static str {
+        StrategyKind::SmartShield.name()
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, rng: &mut BattleRng) -> Action {
+        DefaultStrategy.action(state, player_i, rng)
+    }
+
+    fn shield(&mut self, state: &State, player_i: usize, mv: &'static ChargeMove, _rng: &mut BattleRng) -> bool {
+        self.policy.should_shield(state, player_i, mv)
+    }
+}
+
+#[test]
+fn test_shield_policy() {
+    use crate::battle::Battle;
+    use crate::pokemon::Pokemon;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let battle = Battle::new(String::from("p0"), vec![p0, p2.clone()], String::from("p1"), vec![p1, p2]);
+    let mut state = battle.state().clone();
+    state.player0.num_shields = 1;
+    state.player1.num_shields = 1;
+    let mv = state.player1.poke().charge_move1();
+    let damage = state.player1.poke().calc_charge_move_damage(mv, state.player0.poke(), &state.rules);
+
+    // 削られる割合がしきい値より小さければ使わない
+    let ratio = damage as f64 / state.player0.poke().base_hp() as f64;
+    assert!(!ShieldPolicy { damage_ratio: ratio + 0.1 }.should_shield(&state, 0, mv));
+    assert!(ShieldPolicy { damage_ratio: ratio - 0.1 }.should_shield(&state, 0, mv));
+
+    // 瀕死になるなら必ず使う
+    state.player0.team[0].hp = damage;
+    assert!(ShieldPolicy { damage_ratio: 1.0 }.should_shield(&state, 0, mv));
+
+    // シールドがなければ使えない
+    state.player0.num_shields = 0;
+    assert!(!ShieldPolicy { damage_ratio: 0.0 }.should_shield(&state, 0, mv));
+
+    // ミニゲームの出来が悪いルールではダメージが減るので、瀕死にならなければ使わない
+    state.player0.num_shields = 1;
+    state.rules.cm_bonus = 0.5;
+    assert!(!ShieldPolicy { damage_ratio: 1.0 }.should_shield(&state, 0, mv));
+}
+
+/// スペシャルアタックの選び方を考える戦略。
+///
+/// * 相手にシールドが残っている間は、エネルギーの少ないわざを打ってシールドを使わせる。
+/// * シールドがなくなったら、エネルギーあたりのダメージが大きいわざを打つ。
+/// * 打てるわざでは倒せないが、エネルギーをためれば強いわざで倒せるときは、危なくなければためる。
+pub struct BaitStrategy;
+
+impl BaitStrategy {
+    /// スペシャルアタックのインデックスを選ぶ。Noneならノーマルアタックを使ってエネルギーをためる。
+    pub fn select_charge_move(state: &State, player_i: usize) -> Option<usize> {
+        let p = state.player(player_i);
+        let o = state.opponent(player_i);
+        let poke = p.poke();
+        let opponent = o.poke();
+
+        // (インデックス, 必要なエネルギー, ダメージ)
+        let moves = (0..2)
+            .filter_map(|i| poke.charge_move(i).map(|mv| (i, mv.energy(), poke.calc_charge_move_damage(mv, opponent, &state.rules))))
+            .collect::<Vec<_>>();
+
+        let affordable = moves.iter().filter(|(i, _, _)| poke.can_charge_move(*i)).copied().collect::<Vec<_>>();
+
+        if affordable.is_empty() {
+            return None;
+        }
+
+        // 相手にシールドがある間は一番安いわざでシールドを削る
+        if o.num_shields > 0 {
+            return affordable.iter().min_by_key(|(_, energy, _)| *energy).map(|(i, _, _)| *i);
+        }
+
+        // 打てるわざで倒せるなら、その中で一番安いものを打つ
+        if let Some((i, _, _)) = affordable.iter().filter(|(_, _, damage)| *damage >= opponent.hp()).min_by_key(|(_, energy, _)| *energy) {
+            return Some(*i);
+        }
+
+        let dpe = |(_, energy, damage): &(usize, i32, i32)| *damage as f64 / *energy as f64;
+        let best = moves.iter().max_by(|a, b| dpe(a).total_cmp(&dpe(b))).copied().unwrap();
+        let strongest = moves.iter().max_by_key(|(_, _, damage)| *damage).copied().unwrap();
+
+        // 打てない強いわざで倒せるなら、間に合うかぎりためる
+        if !poke.can_charge_move(strongest.0) && strongest.2 >= opponent.hp() && Self::can_wait(state, player_i, strongest.1) {
+            return None;
+        }
+
+        if poke.can_charge_move(best.0) {
+            return Some(best.0);
+        }
+
+        // 効率の良いわざを待つ余裕がなければ、打てるものを打つ
+        if Self::can_wait(state, player_i, best.1) {
+            None
+        } else {
+            affordable.iter().max_by(|a, b| dpe(a).total_cmp(&dpe(b))).map(|(i, _, _)| *i)
+        }
+    }
+
+    /// エネルギーがenergyたまるまで、ノーマルアタックを使い続けても倒されないか
+    fn can_wait(state: &State, player_i: usize, energy: i32) -> bool {
+        let poke = state.player(player_i).poke();
+        let opponent = state.opponent(player_i).poke();
+
+        // 相手が今打てるスペシャルアタックで倒されるなら待てない
+        let is_lethal = (0..2)
+            .filter(|i| opponent.can_charge_move(*i))
+            .any(|i| opponent.calc_charge_move_damage(opponent.charge_move(i).unwrap(), poke, &state.rules) >= poke.hp());
+
+        if is_lethal {
+            return false;
+        }
+
+        let fast_move = poke.fast_move();
+        let num_fast_moves = (energy - poke.energy + fast_move.energy() - 1) / std::cmp::max(fast_move.energy(), 1);
+        let wait_turns = num_fast_moves * fast_move.turns();
+
+        let op_fast_move = opponent.fast_move();
+        let op_damage = opponent.calc_fast_move_damage(poke, &state.rules) * wait_turns / std::cmp::max(op_fast_move.turns(), 1);
+
+        op_damage < poke.hp()
+    }
+}
+
+impl BattleStrategy for BaitStrategy {
+    fn name(&self) -> &'static str {
+        StrategyKind::Bait.name()
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, _rng: &mut BattleRng) -> Action {
+        match Self::select_charge_move(state, player_i) {
+            Some(i) => Action::ChargeMove(i as u8),
+            None => Action::FastMove,
+        }
+    }
+}
+
+#[test]
+fn test_bait_strategy() {
+    use crate::battle::Battle;
+    use crate::pokemon::Pokemon;
+
+    // ナマズン: どろばくだん(エネルギー小), じしん(エネルギー大)
+    let p0 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", Some(String::from("じしん")), 1474).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+
+    let battle = Battle::new(String::from("p0"), vec![p0], String::from("p1"), vec![p1]);
+    let mut state = battle.state().clone();
+    let (cheap, expensive) = {
+        let poke = state.player0.poke();
+        if poke.charge_move1().energy() < poke.charge_move2().unwrap().energy() { (0, 1) } else { (1, 0) }
+    };
+
+    // エネルギーがなければノーマルアタック
+    assert_eq!(BaitStrategy::select_charge_move(&state, 0), None);
+
+    // シールドがある間は安いわざ
+    state.player0.team[0].energy = 100;
+    assert_eq!(BaitStrategy::select_charge_move(&state, 0), Some(cheap));
+
+    // シールドがなく、どちらでも倒せるなら安いわざ
+    state.player1.num_shields = 0;
+    state.player1.team[0].hp = 1;
+    assert_eq!(BaitStrategy::select_charge_move(&state, 0), Some(cheap));
+
+    // 強いわざでしか倒せず、危なくなければためる
+    let damages = [0, 1].map(|i| state.player0.poke().calc_charge_move_damage(state.player0.poke().charge_move(i).unwrap(), state.player1.poke(), &state.rules));
+    assert!(damages[expensive] > damages[cheap]);
+    state.player1.team[0].hp = damages[expensive];
+    state.player1.team[0].energy = 0;
+    state.player0.team[0].energy = state.player0.poke().charge_move(cheap).unwrap().energy();
+    assert_eq!(BaitStrategy::select_charge_move(&state, 0), None);
+
+    // 倒される危険があるなら打てるわざを打つ
+    state.player0.team[0].hp = 1;
+    assert_eq!(BaitStrategy::select_charge_move(&state, 0), Some(cheap));
+}
+
+/// pokeとopponentが1対1で戦ったときの有利さを0.0～1.0で見積もる。0.5で五分、大きいほどpokeが有利。
+///
+/// お互いのノーマルアタックとスペシャルアタックの1ターンあたりのダメージ(タイプ相性、ステータス、ランク補正を含む)から、
+/// 相手を倒すまでのターン数を比べる。たまっているエネルギーはすぐに打てるダメージとして扱う。
+pub fn matchup_score(poke: &BattlePokemon, opponent: &BattlePokemon, rules: &BattleRules) -> f64 {
+    let turns_to_win = turns_to_faint(poke, opponent, rules);
+    let turns_to_lose = turns_to_faint(opponent, poke, rules);
+
+    turns_to_lose / (turns_to_win + turns_to_lose)
+}
+
+/// attackerがdefenderを倒すまでのおおよそのターン数
+fn turns_to_faint(attacker: &BattlePokemon, defender: &BattlePokemon, rules: &BattleRules) -> f64 {
+    let fast_move = attacker.fast_move();
+    let fast_turns = std::cmp::max(fast_move.turns(), 1) as f64;
+
+    // エネルギーあたりのダメージが一番大きいスペシャルアタック
+    let dpe = (0..2)
+        .filter_map(|i| attacker.charge_move(i))
+        .map(|mv| attacker.calc_charge_move_damage(mv, defender, rules) as f64 / std::cmp::max(mv.energy(), 1) as f64)
+        .fold(0.0, f64::max);
+
+    let dpt = (attacker.calc_fast_move_damage(defender, rules) as f64 + fast_move.energy() as f64 * dpe) / fast_turns;
+    let hp = (defender.hp() as f64 - attacker.energy as f64 * dpe).max(0.0);
+
+    hp / dpt + 1.0
+}
+
+/// ポケモンの交代の判断
+#[derive(Debug, Clone, Copy)]
+pub struct SwitchPolicy {
+    pub margin: f64,  // 自分から交代するのに必要な、今のポケモンとのmatchup_scoreの差
+}
+
+impl Default for SwitchPolicy {
+    fn default() -> Self {
+        Self { margin: 0.2 }
+    }
+}
+
+impl SwitchPolicy {
+    /// 控えのポケモンの中で相手の今のポケモンに一番有利なもののインデックス
+    pub fn best_member(state: &State, player_i: usize) -> Option<usize> {
+        let p = state.player(player_i);
+        let opponent = state.opponent(player_i).poke();
+
+        (0..p.team.len())
+            .filter(|i| *i != p.cur_poke && !p.team[*i].is_faint())
+            .max_by(|a, b| matchup_score(&p.team[*a], opponent, &state.rules).total_cmp(&matchup_score(&p.team[*b], opponent, &state.rules)))
+    }
+
+    /// 自分から交代するなら交代先のインデックスを返す。
+    /// 交代すると1分間交代できなくなるので、今のポケモンが不利で、控えがmargin以上有利なときだけ交代する。
+    pub fn should_switch(&self, state: &State, player_i: usize) -> Option<usize> {
+        let p = state.player(player_i);
+
+        if p.switch_ms > 0 || p.in_fast_move {
+            return None;
+        }
+
+        let opponent = state.opponent(player_i).poke();
+        let cur_score = matchup_score(p.poke(), opponent, &state.rules);
+
+        if cur_score >= 0.5 {
+            return None;
+        }
+
+        Self::best_member(state, player_i)
+            .filter(|i| matchup_score(&p.team[*i], opponent, &state.rules) >= cur_score + self.margin)
+    }
+}
+
+/// Baitの行動、ShieldPolicy、SwitchPolicyを組み合わせた戦略
+#[derive(Default)]
+pub struct SmartStrategy {
+    pub shield_policy: ShieldPolicy,
+    pub switch_policy: SwitchPolicy,
+}
+
+impl BattleStrategy for SmartStrategy {
+    fn name(&self) -> &'static str {
+        StrategyKind::Smart.name()
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, rng: &mut BattleRng) -> Action {
+        match self.switch_policy.should_switch(state, player_i) {
+            Some(i) => Action::SwitchPokemon(i as u8),
+            None => BaitStrategy.action(state, player_i, rng),
+        }
+    }
+
+    fn shield(&mut self, state: &State, player_i: usize, mv: &'static ChargeMove, _rng: &mut BattleRng) -> bool {
+        self.shield_policy.should_shield(state, player_i, mv)
+    }
+
+    fn switch(&mut self, state: &State, player_i: usize, _rng: &mut BattleRng) -> usize {
+        SwitchPolicy::best_member(state, player_i).unwrap_or(0)
+    }
+}
+
+#[test]
+fn test_switch_policy() {
+    use crate::battle::Battle;
+    use crate::pokemon::Pokemon;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let battle = Battle::new(String::from("p0"), vec![p0, p2.clone(), p1.clone()], String::from("p1"), vec![p1]);
+    let mut state = battle.state().clone();
+
+    // エスパータイプのココロモリはあくタイプのブラッキーに不利
+    let score = matchup_score(state.player0.poke(), state.player1.poke(), &state.rules);
+    assert!(score < 0.5);
+    assert!((score + matchup_score(state.player1.poke(), state.player0.poke(), &state.rules) - 1.0).abs() < 1e-9);
+
+    let best = SwitchPolicy::best_member(&state, 0).unwrap();
+    assert_ne!(best, 0);
+    assert!(matchup_score(&state.player0.team[best], state.player1.poke(), &state.rules) > score);
+
+    // 交代できない間は交代しない
+    state.player0.switch_ms = 1000;
+    assert_eq!(SwitchPolicy { margin: 0.0 }.should_switch(&state, 0), None);
+
+    state.player0.switch_ms = 0;
+    assert_eq!(SwitchPolicy { margin: 0.0 }.should_switch(&state, 0), Some(best));
+    assert_eq!(SwitchPolicy { margin: 1.0 }.should_switch(&state, 0), None);
+
+    // 瀕死のポケモンは選ばない
+    state.player0.team[best].hp = 0;
+    assert_ne!(SwitchPolicy::best_member(&state, 0), Some(best));
+}
+
+/// 指定したターンだけ行動やシールドの判断を差し替える戦略。
+/// 記録したバトルを巻き戻して「もしこうしていたら」を試すのに使う。それ以外はbaseの戦略にしたがう。
+pub struct OverrideStrategy {
+    pub base: Box<dyn BattleStrategy>,
+    pub turn: i32,  // 差し替えるターン(State::turn)
+    pub action: Option<Action>,  // turnでとる行動
+    pub shield: Option<bool>,  // turn以降に最初にシールドを使うか聞かれたときの答え
+}
+
+impl OverrideStrategy {
+    pub fn new(base: Box<dyn BattleStrategy>, turn: i32, action: Option<Action>, shield: Option<bool>) -> Self {
+        Self { base, turn, action, shield }
+    }
+}
+
+impl BattleStrategy for OverrideStrategy {
+    fn name(&self) -> &'static str {
+        self.base.name()
+    }
+
+    fn action(&mut self, state: &State, player_i: usize, rng: &mut BattleRng) -> Action {
+        if state.turn == self.turn {
+            if let Some(action) = self.action.take() {
+                return action;
+            }
+        }
+
+        self.base.action(state, player_i, rng)
+    }
+
+    fn shield(&mut self, state: &State, player_i: usize, mv: &'static ChargeMove, rng: &mut BattleRng) -> bool {
+        if state.turn >= self.turn {
+            if let Some(shield) = self.shield.take() {
+                return shield;
+            }
+        }
+
+        self.base.shield(state, player_i, mv, rng)
+    }
+
+    fn switch(&mut self, state: &State, player_i: usize, rng: &mut BattleRng) -> usize {
+        self.base.switch(state, player_i, rng)
+    }
+}
+
+#[test]
+fn test_override_strategy() {
+    use crate::battle::{Battle, Event};
+    use crate::pokemon::Pokemon;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+
+    let mut battle = Battle::new(String::from("p0"), vec![p0], String::from("p1"), vec![p1]);
+    battle.set_seed(1);
+    battle.start();
+
+    // 最初にシールドを使ったターンまで戻して、シールドを使わなかったことにする
+    let turn = battle.logs.iter().position(|log| log.events0.iter().any(|e| matches!(e, Event::Shield))).unwrap();
+    let base_turn = battle.states[turn].turn;
+    let strategies: [Box<dyn BattleStrategy>; 2] = [
+        Box::new(OverrideStrategy::new(StrategyKind::Default.create(), base_turn, None, Some(false))),
+        StrategyKind::Default.create(),
+    ];
+
+    let mut branch = battle.branch(turn, strategies);
+    branch.start();
+
+    assert!(!branch.logs[turn].events0.iter().any(|e| matches!(e, Event::Shield)));
+    assert!(branch.states[turn + 1].player0.num_shields > battle.states[turn + 1].player0.num_shields);
+}
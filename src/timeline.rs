@@ -0,0 +1,142 @@
+//! バトルの経過を文字で描いたグラフ(タイムライン)
+//! 横軸が時間で、プレイヤーごとに場に出ているポケモン、HP、エネルギー、出来事の行を並べる。
+//! 1文字が何ターン分になるかは、バトルの長さと表示する幅から決める。
+
+use crate::battle::{Battle, Event, State};
+use crate::utils::{jp_width, jp_fixed_width_string};
+
+/// 割合を表す文字。左ほど小さい
+const LEVELS: [char; 10] = [' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+/// 0.0～1.0の割合を文字にする。0より大きければ空白にはしない
+fn level_char(ratio: f64) -> char {
+    if ratio <= 0.0 {
+        return LEVELS[0];
+    }
+
+    let i = (ratio * (LEVELS.len() - 1) as f64).ceil() as usize;
+    LEVELS[i.clamp(1, LEVELS.len() - 1)]
+}
+
+/// 1文字の間に起きた出来事を表す文字。重要なものを優先する
+fn event_char(events: &[&Event], player_i: usize, state: &State) -> char {
+    let p = state.player(player_i);
+
+    if events.iter().any(|e| matches!(e, Event::Faint)) {
+        'X'
+    } else if events.iter().any(|e| matches!(e, Event::Switch(_))) {
+        '>'
+    } else if events.iter().any(|e| matches!(e, Event::Shield)) {
+        'S'
+    } else if let Some(Event::ChargeMove(mv, _, _)) = events.iter().rev().find(|e| matches!(e, Event::ChargeMove(..))) {
+        // どちらのスペシャルアタックか
+        if p.poke().charge_move2().map(|mv2| mv2.no() == mv.no()).unwrap_or(false) { '2' } else { '1' }
+    } else {
+        ' '
+    }
+}
+
+/// battleのタイムラインを行ごとに返す。num_colsはグラフの幅(文字数)
+pub fn format_timeline(battle: &Battle, num_cols: usize) -> Vec<String> {
+    let num_turns = battle.logs.len();
+    let num_cols = num_cols.max(1);
+    let turns_per_col = num_turns.div_ceil(num_cols).max(1);
+    let num_cols = num_turns.div_ceil(turns_per_col);
+
+    let start = &battle.states[0];
+    let labels = ["HP", "EN", "EV", "PK"];
+    let label_width = [&start.player0, &start.player1].iter()
+        .map(|p| jp_width(&p.name)).chain(labels.iter().map(|l| l.len() + 2)).max().unwrap();
+
+    // 各列の最後の状態と、その列の間に起きた出来事
+    let cols = (0..num_cols).map(|c| {
+        let from = c * turns_per_col;
+        let to = std::cmp::min(from + turns_per_col, num_turns);
+        let events = [0, 1].map(|player_i| {
+            battle.logs[from..to].iter()
+                .flat_map(|log| if player_i == 0 { &log.events0 } else { &log.events1 })
+                .collect::<Vec<_>>()
+        });
+
+        (&battle.states[to], events)
+    }).collect::<Vec<_>>();
+
+    let mut lines = vec![];
+
+    // 時間の目盛り。10文字ごとに経過秒数を書く
+    let mut axis = String::new();
+
+    for c in (0..num_cols).step_by(10) {
+        let label = format!("{}s", cols[c].0.elapsed_ms / 1000);
+        axis += &format!("{:<10}", label);
+    }
+
+    lines.push(format!("{} |{}", jp_fixed_width_string("", label_width), axis.trim_end()));
+
+    for player_i in 0..2 {
+        let name = &start.player(player_i).name;
+        lines.push(name.clone());
+
+        let rows: [String; 4] = [
+            cols.iter().map(|(state, _)| {
+                let poke = state.player(player_i).poke();
+                level_char(poke.hp() as f64 / poke.base_hp() as f64)
+            }).collect(),
+
+            cols.iter().map(|(state, _)| level_char(state.player(player_i).poke().energy as f64 / 100.0)).collect(),
+
+            cols.iter().map(|(state, events)| event_char(&events[player_i], player_i, state)).collect(),
+
+            cols.iter().map(|(state, _)| {
+                let p = state.player(player_i);
+                if p.is_ended() { ' ' } else { char::from_digit((p.cur_poke + 1) as u32, 10).unwrap_or('?') }
+            }).collect(),
+        ];
+
+        for (label, row) in labels.iter().zip(rows) {
+            lines.push(format!("{} |{}|", jp_fixed_width_string(&format!("  {}", label), label_width), row));
+        }
+    }
+
+    // 凡例
+    for player_i in 0..2 {
+        let p = start.player(player_i);
+        let pokes = p.team.iter().enumerate().map(|(i, poke)| format!("{}: {}", i + 1, poke.name())).collect::<Vec<_>>();
+        lines.push(format!("PK({}) {}", p.name, pokes.join(", ")));
+    }
+
+    lines.push(format!("1文字 = {}ターン, HP/EN: 少 {} 多, EV: 1/2 スペシャルアタック, S シールド, > 交代, X 瀕死",
+                       turns_per_col, LEVELS[1..].iter().collect::<String>()));
+
+    lines
+}
+
+#[test]
+fn test_format_timeline() {
+    use crate::pokemon::Pokemon;
+
+    let p0 = Pokemon::new("ココロモリ", None, (10, 9, 12), "エアスラッシュ", "サイコファング", None, 1489).unwrap();
+    let p1 = Pokemon::new("ブラッキー", None, (2, 14, 0), "バークアウト", "あくのはどう", None, 1498).unwrap();
+    let p2 = Pokemon::new("ナマズン", None, (8, 15, 14), "みずでっぽう", "どろばくだん", None, 1474).unwrap();
+
+    let mut battle = Battle::new(String::from("p0"), vec![p0, p2.clone()], String::from("p1"), vec![p1, p2]);
+    battle.set_seed(1);
+    battle.start();
+
+    let lines = format_timeline(&battle, 40);
+    let rows = lines.iter().filter(|line| line.ends_with('|')).collect::<Vec<_>>();
+
+    // 各プレイヤーにHP, EN, EV, PKの行があり、すべて同じ幅になる
+    assert_eq!(rows.len(), 8);
+    assert!(rows.iter().all(|row| jp_width(row) == jp_width(rows[0])));
+    assert!(jp_width(rows[0]) <= "    HP |".len() + 40 + 1);
+
+    // 最初は満タン、負けた側は最後に瀕死
+    let hp0 = rows[0].split('|').nth(1).unwrap();
+    assert!(hp0.starts_with('@'));
+    assert!(rows.iter().any(|row| row.contains('X')));
+
+    assert_eq!(level_char(0.0), ' ');
+    assert_eq!(level_char(0.01), '.');
+    assert_eq!(level_char(1.0), '@');
+}
@@ -112,6 +112,7 @@ pub const TYPE_NAMES: [&str; NUM_TYPES] = [
 ];
 
 impl Type {
+    #[allow(clippy::needless_range_loop)]
     pub fn print_effect_table(type2: Option<Type>) {
         println!();
 
@@ -247,6 +248,7 @@ impl Type {
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn test_type_effect_bonus() {
     let mut effects = vec![];
     for v in TYPE_EFFECT_ARR {
@@ -6,6 +6,7 @@ pub struct NameItem {
 }
 
 impl SkimItem for NameItem {
+    #[allow(mismatched_lifetime_syntaxes)]
     fn text(&self) -> Cow<str> {
         Cow::Borrowed(&self.search_text)
     }
@@ -40,3 +41,50 @@ pub fn jp_fixed_width_string(s: &str, w: usize) -> String {
     let num_spaces = std::cmp::max(w as isize - jp_width(s) as isize, 0);
     format!("{}{:<width$}", s, "", width=num_spaces as usize)
 }
+
+/// valuesの範囲を"1520-1540"のように表す。最小と最大が同じなら1つの値だけ
+pub fn format_range<T: PartialOrd + Copy>(values: impl IntoIterator<Item = T>, format: impl Fn(T) -> String) -> String {
+    let mut iter = values.into_iter();
+
+    let first = match iter.next() {
+        None => return String::new(),
+        Some(v) => v,
+    };
+
+    let (min, max) = iter.fold((first, first), |(min, max), v| {
+        (if v < min { v } else { min }, if max < v { v } else { max })
+    });
+
+    if min == max { format(min) } else { format!("{}-{}", format(min), format(max)) }
+}
+
+/// itemsの各要素にfを適用した結果を返す。
+/// 使えるCPUの数だけスレッドに分けて実行するが、結果の順番はitemsと同じになる。
+pub fn par_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let num_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = items.len().div_ceil(num_threads).max(1);
+    let f = &f;
+
+    std::thread::scope(|s| {
+        let handles = items.chunks(chunk_size)
+            .map(|chunk| s.spawn(move || chunk.iter().map(f).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+#[test]
+fn test_par_map() {
+    let v = (0..100).collect::<Vec<i32>>();
+    assert_eq!(par_map(&v, |x| x * 2), (0..100).map(|x| x * 2).collect::<Vec<_>>());
+    assert!(par_map(&Vec::<i32>::new(), |x| *x).is_empty());
+}
+
+#[test]
+fn test_format_range() {
+    assert_eq!(format_range([1530, 1520, 1540], |v| v.to_string()), "1520-1540");
+    assert_eq!(format_range([9, 9], |v| format!("{:>2}", v)), " 9");
+    assert_eq!(format_range([20.5, 20.0], |v| format!("{:.1}", v)), "20.0-20.5");
+    assert_eq!(format_range(Vec::<i32>::new(), |v| v.to_string()), "");
+}